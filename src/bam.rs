@@ -1,63 +1,379 @@
 use anyhow::{Context, Result};
+use bio::io::fasta;
 use log::info;
 use rust_htslib::bam::{self, Read};
 use std::collections::HashMap;
 
+/// Number of equal-width GC-fraction buckets used to build the expected/observed distributions
+/// for Benjamini-Speed-style bias correction.
+const GC_BUCKETS: usize = 100;
+
+/// Fragment-length window used both to sample the expected genome-wide GC distribution and to
+/// measure each read's observed GC fraction at its counted fragment position.
+const GC_FRAGMENT_LENGTH: u32 = 200;
+
+/// Stride used when sliding the fragment-length window across each chromosome to build the
+/// expected GC distribution; scanning every single position is unnecessary for a stable estimate.
+const GC_SCAN_STRIDE: u32 = 25;
+
+fn gc_bucket(gc_fraction: f64) -> usize {
+    ((gc_fraction * GC_BUCKETS as f64) as usize).min(GC_BUCKETS - 1)
+}
+
+/// Fraction of `G`/`C` bases among defined (non-`N`) bases in `seq`, or `None` if `seq` is
+/// entirely `N` (undefined sequence, e.g. an assembly gap).
+fn gc_fraction(seq: &[u8]) -> Option<f64> {
+    let mut gc = 0usize;
+    let mut defined = 0usize;
+
+    for &base in seq {
+        match base.to_ascii_uppercase() {
+            b'G' | b'C' => {
+                gc += 1;
+                defined += 1;
+            }
+            b'A' | b'T' => defined += 1,
+            _ => {} // N or other ambiguity codes don't count as defined sequence
+        }
+    }
+
+    if defined == 0 {
+        None
+    } else {
+        Some(gc as f64 / defined as f64)
+    }
+}
+
+/// Per-GC-bucket reweighting used to correct fragment counts for GC-content bias: a read whose
+/// fragment-length window falls in a GC bucket that is over-represented among reads relative to
+/// the mappable genome gets down-weighted, and vice versa.
+///
+/// Fit by comparing two distributions over `GC_BUCKETS` buckets: the *expected* distribution,
+/// built by sliding a `GC_FRAGMENT_LENGTH`-wide window across the mappable genome (from the
+/// reference FASTA), and the *observed* distribution, built from the GC fraction of each read's
+/// fragment-length window in the treatment BAM. `weight(gc) = expected_count(gc) /
+/// observed_count(gc)`, and is `0.0` where no reads were observed for that bucket.
+///
+/// This fragment-level reweighting is the only `--reference` GC correction this crate ships; it
+/// intentionally replaces an earlier bin-level, 20-quantile-stratum median-ratio correction (fit
+/// on binned counts, excluding undefined-sequence bins) rather than running alongside it. Per-read
+/// reweighting generalizes the same expected-vs-observed idea to individual fragments instead of
+/// pre-aggregated bins, needs no binning-resolution choice, and plugs into `count_reads_in_bins`
+/// as a simple per-read multiplier, so the two approaches would otherwise duplicate the same
+/// correction through two different code paths for no added benefit.
+struct GcBiasModel {
+    weights: [f64; GC_BUCKETS],
+}
+
+impl GcBiasModel {
+    fn fit(reference_path: &str, bam_path: &str, read_filter: &ReadFilter) -> Result<Self> {
+        info!(
+            "Fitting GC-bias model from reference {} against {}",
+            reference_path, bam_path
+        );
+
+        let mut reference = fasta::IndexedReader::from_file(reference_path).context(format!(
+            "Failed to open indexed reference FASTA: {}",
+            reference_path
+        ))?;
+
+        let mut bam_reader =
+            bam::Reader::from_path(bam_path).context(format!("Failed to open BAM file: {}", bam_path))?;
+        let header = bam_reader.header().to_owned();
+
+        let mut expected_counts = [0u64; GC_BUCKETS];
+        let mut observed_counts = [0u64; GC_BUCKETS];
+
+        // Expected distribution: slide the fragment-length window across every chromosome that
+        // both the BAM header and the reference agree on.
+        for tid in 0..header.target_count() {
+            let chrom = std::str::from_utf8(header.tid2name(tid))
+                .context("Failed to parse chromosome name")?
+                .to_string();
+            let length = match header.target_len(tid) {
+                Some(len) => len as u32,
+                None => continue,
+            };
+            if reference.fetch(&chrom, 0, 1).is_err() {
+                continue; // reference doesn't carry this contig; skip it
+            }
+
+            let mut start = 0u32;
+            let mut seq = Vec::new();
+            while start + GC_FRAGMENT_LENGTH <= length {
+                if reference
+                    .fetch(&chrom, start as u64, (start + GC_FRAGMENT_LENGTH) as u64)
+                    .is_ok()
+                    && reference.read(&mut seq).is_ok()
+                {
+                    if let Some(gc) = gc_fraction(&seq) {
+                        expected_counts[gc_bucket(gc)] += 1;
+                    }
+                }
+                start += GC_SCAN_STRIDE;
+            }
+        }
+
+        // Observed distribution: the GC fraction of the fragment-length window at each read's
+        // counted fragment position — the same `read_filter`-filtered, fragment-midpoint/extended
+        // position `count_reads_in_bins` later looks GC-bias weights up at, so the expected and
+        // observed distributions (and the reads contributing to the latter) describe the same
+        // coordinate system and read population.
+        let mut seq = Vec::new();
+        for record in bam_reader.records() {
+            let rec = record?;
+            let pos = match read_filter.fragment_position(&rec) {
+                Some(pos) => pos,
+                None => continue,
+            };
+
+            let tid = rec.tid();
+            if tid < 0 {
+                continue;
+            }
+            let chrom = std::str::from_utf8(header.tid2name(tid as u32))
+                .context("Failed to parse chromosome name")?
+                .to_string();
+            let length = match header.target_len(tid as u32) {
+                Some(len) => len as u32,
+                None => continue,
+            };
+
+            let end = (pos + GC_FRAGMENT_LENGTH).min(length);
+            if pos >= end {
+                continue;
+            }
+
+            if reference.fetch(&chrom, pos as u64, end as u64).is_ok() && reference.read(&mut seq).is_ok() {
+                if let Some(gc) = gc_fraction(&seq) {
+                    observed_counts[gc_bucket(gc)] += 1;
+                }
+            }
+        }
+
+        let mut weights = [0.0; GC_BUCKETS];
+        for bucket in 0..GC_BUCKETS {
+            weights[bucket] = if observed_counts[bucket] > 0 {
+                expected_counts[bucket] as f64 / observed_counts[bucket] as f64
+            } else {
+                0.0
+            };
+        }
+
+        info!("Fitted GC-bias weights across {} buckets", GC_BUCKETS);
+
+        Ok(Self { weights })
+    }
+
+    fn weight_for(&self, gc_fraction: f64) -> f64 {
+        self.weights[gc_bucket(gc_fraction)]
+    }
+}
+
+/// Configures how a read or read pair is turned into a single counted fragment position:
+/// quality/flag filtering, properly-paired fragment-midpoint counting, and optional single-end
+/// extension. Threaded through `BamProcessor` so `count_total_reads`, `count_reads_in_bins`, and
+/// `compute_local_background` all agree on what counts as a fragment.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadFilter {
+    pub min_mapq: u8,
+    pub skip_duplicates: bool,
+    pub skip_secondary: bool,
+    pub skip_supplementary: bool,
+    pub skip_qc_fail: bool,
+    /// Fixed fragment length to extend single-end (or non-properly-paired) reads to before
+    /// taking the midpoint; `None` leaves such reads counted at their leftmost aligned position
+    /// (`pos`), on either strand.
+    pub extend_to: Option<u32>,
+}
+
+impl Default for ReadFilter {
+    fn default() -> Self {
+        Self {
+            min_mapq: 0,
+            skip_duplicates: true,
+            skip_secondary: true,
+            skip_supplementary: true,
+            skip_qc_fail: true,
+            extend_to: None,
+        }
+    }
+}
+
+impl ReadFilter {
+    /// Returns the genomic position to assign `rec` to as a single counted fragment, or `None`
+    /// if `rec` fails filtering. Properly-paired reads are counted once, at the midpoint of the
+    /// fragment spanned by `pos` and `insert_size`, using only the mate with the positive insert
+    /// size so the pair isn't double-counted. All other reads are counted at their leftmost
+    /// aligned position (`pos`), or, with `extend_to` set, at the midpoint of the fragment
+    /// extended (strand-aware) from the read's 5' end.
+    pub fn fragment_position(&self, rec: &bam::Record) -> Option<u32> {
+        if rec.is_unmapped() {
+            return None;
+        }
+        if rec.mapq() < self.min_mapq {
+            return None;
+        }
+        if self.skip_duplicates && rec.is_duplicate() {
+            return None;
+        }
+        if self.skip_secondary && rec.is_secondary() {
+            return None;
+        }
+        if self.skip_supplementary && rec.is_supplementary() {
+            return None;
+        }
+        if self.skip_qc_fail && rec.is_quality_check_failed() {
+            return None;
+        }
+
+        let pos = rec.pos().max(0) as u32;
+
+        if rec.is_proper_pair() && rec.insert_size() > 0 {
+            let midpoint = pos + (rec.insert_size() as u32) / 2;
+            return Some(midpoint);
+        }
+        if rec.is_proper_pair() && rec.insert_size() <= 0 {
+            // The mate with the positive insert size owns the fragment; skip this one so the
+            // pair is only counted once.
+            return None;
+        }
+
+        match self.extend_to {
+            Some(length) if rec.is_reverse() => {
+                let end = pos + rec.seq_len() as u32;
+                Some(end.saturating_sub(length / 2))
+            }
+            Some(length) => Some(pos + length / 2),
+            None => Some(pos),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct GenomicRange {
     pub chrom: String,
     pub start: u32,
     pub end: u32,
     pub posterior_prob: f64,
+    /// Local (per-peak) false discovery rate, set when selection runs under `--fdr` control.
+    pub local_fdr: Option<f64>,
 }
 
 pub struct BamProcessor {
     bam_path: String,
     control_path: Option<String>,
+    reference_path: Option<String>,
+    read_filter: ReadFilter,
     total_reads: usize,
     control_reads: Option<usize>,
+    /// Per-chromosome mapped-read counts from the BAM index, when one is available. Lets the
+    /// by-chromosome parallel loop in `count_reads_in_bins` skip chromosomes with no mapped
+    /// reads without opening an `IndexedReader` for them.
+    chrom_read_counts: HashMap<String, u64>,
+    gc_bias_model: Option<GcBiasModel>,
 }
 
 impl BamProcessor {
-    pub fn new(bam_path: &str, control_path: Option<&str>) -> Result<Self> {
-        let total_reads = Self::count_total_reads(bam_path)?;
+    pub fn new(
+        bam_path: &str,
+        control_path: Option<&str>,
+        reference_path: Option<&str>,
+        read_filter: ReadFilter,
+    ) -> Result<Self> {
+        let total_reads = Self::count_total_reads(bam_path, &read_filter)?;
 
         let control_reads = if let Some(control) = control_path {
-            Some(Self::count_total_reads(control)?)
+            Some(Self::count_total_reads(control, &read_filter)?)
         } else {
             None
         };
 
+        let chrom_read_counts = Self::index_stats(bam_path)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(chrom, mapped, _unmapped)| (chrom, mapped))
+            .collect();
+
+        let gc_bias_model = match reference_path {
+            Some(reference) => Some(GcBiasModel::fit(reference, bam_path, &read_filter)?),
+            None => None,
+        };
+
         Ok(Self {
             bam_path: bam_path.to_string(),
             control_path: control_path.map(String::from),
+            reference_path: reference_path.map(String::from),
+            read_filter,
             total_reads,
             control_reads,
+            chrom_read_counts,
+            gc_bias_model,
         })
     }
 
-    fn count_total_reads(path: &str) -> Result<usize> {
+    /// Returns `(chrom, mapped, unmapped)` per reference straight from the BAM index (the same
+    /// numbers `samtools idxstats` reports), or `None` if `path` has no `.bai`/`.csi` index.
+    fn index_stats(path: &str) -> Option<Vec<(String, u64, u64)>> {
+        let mut reader = rust_htslib::bam::IndexedReader::from_path(path).ok()?;
+        let stats = reader.index_stats().ok()?;
+        Some(
+            stats
+                .into_iter()
+                .map(|(chrom, _target_len, mapped, unmapped)| (chrom, mapped, unmapped))
+                .collect(),
+        )
+    }
+
+    /// Total counted-fragment count for `path`, used as the library-size denominator for scale
+    /// factors and CPM normalization. Always applies `read_filter` via a full linear scan, so this
+    /// agrees with the per-bin counts `count_reads_in_bins` and `compute_local_background`
+    /// produce with the same filter; the BAM index's raw `mapped` totals (`index_stats`) can't be
+    /// used here since they count every mapped read regardless of MAPQ/duplicate/secondary flags.
+    ///
+    /// This gives up the O(references) startup cost an idxstats-only fast path would have had, in
+    /// favor of a correct count: `read_filter`'s MAPQ/duplicate/secondary exclusions aren't stored
+    /// anywhere in the index, so there's no way to go from idxstats' raw `mapped` count to a
+    /// filtered one without re-deriving the excluded count from a scan — at which point the index
+    /// bought nothing. An O(reads) scan here is the accepted cost of agreeing with the rest of the
+    /// pipeline.
+    fn count_total_reads(path: &str, read_filter: &ReadFilter) -> Result<usize> {
         let mut bam =
             bam::Reader::from_path(path).context(format!("Failed to open BAM file: {}", path))?;
 
         let mut count = 0;
         for record in bam.records() {
-            let _rec = record?;
-            count += 1;
+            let rec = record?;
+            if read_filter.fragment_position(&rec).is_some() {
+                count += 1;
+            }
         }
 
         Ok(count)
     }
 
+    /// Mapped-read count for a single chromosome from the BAM index, if one was available at
+    /// construction time.
+    pub fn chrom_read_count(&self, chrom: &str) -> Option<u64> {
+        self.chrom_read_counts.get(chrom).copied()
+    }
+
     pub fn total_reads(&self) -> usize {
         self.total_reads
     }
 
-    pub fn count_reads_in_bins(&self, bins: &[GenomicRange]) -> Result<Vec<(GenomicRange, usize)>> {
+    pub fn control_reads(&self) -> Option<usize> {
+        self.control_reads
+    }
+
+    pub fn count_reads_in_bins(
+        &self,
+        bins: &[GenomicRange],
+    ) -> Result<Vec<(GenomicRange, usize, f64)>> {
         use rayon::prelude::*;
         use rust_htslib::bam::IndexedReader;
         info!(
-            "Counting reads in {} bins using fast arithmetic bin assignment",
+            "Counting reads in {} bins using binary-search bin assignment",
             bins.len()
         );
 
@@ -71,9 +387,15 @@ impl BamProcessor {
         }
 
         // Parallelize by chromosome, collect local results
-        let per_chrom_results: Vec<Vec<(usize, usize)>> = chrom_bins
+        let per_chrom_results: Vec<Vec<(usize, f64)>> = chrom_bins
             .par_iter()
             .map(|(chrom, bin_list)| {
+                // Skip chromosomes the index says have no mapped reads at all, without paying
+                // for an `IndexedReader` + fetch.
+                if self.chrom_read_count(chrom) == Some(0) {
+                    return Vec::new();
+                }
+
                 let mut bam =
                     IndexedReader::from_path(&self.bam_path).expect("Failed to open BAM file");
                 let header = bam.header().to_owned();
@@ -86,29 +408,67 @@ impl BamProcessor {
                     None => return Vec::new(),
                 };
                 let min_start = bin_list[0].1.start;
-                let bin_size = bin_list[0].1.end - bin_list[0].1.start;
                 let num_bins = bin_list.len();
                 let max_end = bin_list[num_bins - 1].1.end;
                 if bam.fetch((tid, min_start, max_end)).is_err() {
                     return Vec::new();
                 }
-                let mut local_counts = vec![0usize; num_bins];
+
+                // `bin_list` is already sorted by `start` (it's built by walking `bins` in
+                // order), but blacklist/regions filtering (`Genome::create_bins`) can leave gaps
+                // between bins, so a bin can no longer be found by fixed-stride arithmetic —
+                // binary search the actual boundaries instead.
+                let bin_starts: Vec<u32> = bin_list.iter().map(|(_, bin)| bin.start).collect();
+
+                // Opened once per chromosome (not per read) when GC-bias correction is enabled.
+                let mut reference = self
+                    .gc_bias_model
+                    .as_ref()
+                    .and_then(|_| self.reference_path.as_ref())
+                    .and_then(|path| fasta::IndexedReader::from_file(path).ok());
+                let chrom_length = header.target_len(tid);
+
+                let mut local_counts = vec![0.0f64; num_bins];
+                let mut seq = Vec::new();
                 for rec in bam.records() {
                     let rec = match rec {
                         Ok(r) => r,
                         Err(_) => continue,
                     };
-                    if rec.is_unmapped() {
+                    let pos = match self.read_filter.fragment_position(&rec) {
+                        Some(pos) => pos,
+                        None => continue,
+                    };
+                    if pos < min_start || pos >= max_end {
                         continue;
                     }
-                    let pos = rec.pos() as u32;
-                    if pos < min_start {
+                    // The last bin with `start <= pos`; reject it if `pos` actually falls in a
+                    // gap past that bin's `end` (a dropped blacklisted/non-whitelisted region).
+                    let candidate = bin_starts.partition_point(|&start| start <= pos);
+                    if candidate == 0 {
                         continue;
                     }
-                    let bin_idx = ((pos - min_start) / bin_size) as usize;
-                    if bin_idx < num_bins {
-                        local_counts[bin_idx] += 1;
+                    let bin_idx = candidate - 1;
+                    if pos >= bin_list[bin_idx].1.end {
+                        continue;
                     }
+
+                    let weight = match (&self.gc_bias_model, &mut reference, chrom_length) {
+                        (Some(model), Some(reference), Some(chrom_length)) => {
+                            let window_end = (pos + GC_FRAGMENT_LENGTH).min(chrom_length as u32);
+                            if pos < window_end
+                                && reference.fetch(chrom, pos as u64, window_end as u64).is_ok()
+                                && reference.read(&mut seq).is_ok()
+                            {
+                                gc_fraction(&seq).map(|gc| model.weight_for(gc)).unwrap_or(1.0)
+                            } else {
+                                1.0
+                            }
+                        }
+                        _ => 1.0,
+                    };
+
+                    local_counts[bin_idx] += weight;
                 }
                 // Return (global_idx, count) for each bin in this chromosome
                 bin_list
@@ -119,91 +479,46 @@ impl BamProcessor {
             })
             .collect();
 
-        // Merge local results into global bin_counts
-        let mut bin_counts = vec![0; bins.len()];
+        // Merge local results into global bin_counts, rounding the GC-reweighted floats to
+        // integer read counts.
+        let mut bin_counts = vec![0usize; bins.len()];
         for chrom_result in per_chrom_results {
             for (global_idx, count) in chrom_result {
-                bin_counts[global_idx] = count;
+                bin_counts[global_idx] = count.round() as usize;
             }
         }
 
-        // If control_path is set, normalize to control
-        if let (Some(control_path), Some(_)) = (&self.control_path, &self.control_reads) {
-            self.normalize_to_control(bins, &mut bin_counts, control_path)?;
-        }
+        // Estimate each bin's local background rate MACS-style rather than subtracting a flat,
+        // globally-scaled control count; the Bayesian model uses this directly as its local noise
+        // expectation.
+        let expected_backgrounds = self.compute_local_background(bins)?;
 
         let result = bins
             .iter()
             .cloned()
-            .zip(bin_counts.iter().cloned())
+            .zip(bin_counts)
+            .zip(expected_backgrounds)
+            .map(|((bin, count), expected_background)| (bin, count, expected_background))
             .collect();
 
         Ok(result)
     }
 
-    fn normalize_to_control(
-        &self,
-        bins: &[GenomicRange],
-        bin_counts: &mut [usize],
-        control_path: &str,
-    ) -> Result<()> {
-        info!("Normalizing counts using control BAM: {}", control_path);
-
-        let mut chrom_bins: HashMap<String, Vec<(usize, &GenomicRange)>> = HashMap::new();
-        for (i, bin) in bins.iter().enumerate() {
-            chrom_bins
-                .entry(bin.chrom.clone())
-                .or_default()
-                .push((i, bin));
-        }
-
-        let mut control_counts = vec![0; bins.len()];
-
-        let mut control_bam = bam::Reader::from_path(control_path)
-            .context(format!("Failed to open control BAM file: {}", control_path))?;
-
-        let target_names: Vec<Vec<u8>> = control_bam
-            .header()
-            .target_names()
-            .iter()
-            .map(|&name| name.to_vec())
-            .collect();
-
-        for record in control_bam.records() {
-            let rec = record?;
-
-            if rec.is_unmapped() {
-                continue;
-            }
-
-            let tid = rec.tid();
-            let chrom = if tid >= 0 && (tid as usize) < target_names.len() {
-                std::str::from_utf8(&target_names[tid as usize])?.to_string()
-            } else {
-                continue;
-            };
-
-            let pos = rec.pos() as u32;
-
-            if let Some(chrom_bin_list) = chrom_bins.get(&chrom) {
-                for (bin_idx, bin) in chrom_bin_list {
-                    if pos >= bin.start && pos < bin.end {
-                        control_counts[*bin_idx] += 1;
-                    }
-                }
-            }
-        }
-
-        let scale_factor = self.total_reads as f64 / self.control_reads.unwrap_or(1) as f64;
-
-        for i in 0..bin_counts.len() {
-            let treatment_count = bin_counts[i] as f64;
-            let control_count = control_counts[i] as f64 * scale_factor;
+    /// Estimates each bin's MACS-style local background rate (the control BAM's density when one
+    /// was supplied, otherwise the treatment BAM's own density), scaled by the treatment/control
+    /// library-size ratio. See `background_model::compute_lambda_local`.
+    fn compute_local_background(&self, bins: &[GenomicRange]) -> Result<Vec<f64>> {
+        let source_path = self.control_path.as_deref().unwrap_or(&self.bam_path);
+        let size_factor = match &self.control_path {
+            Some(_) => self.total_reads as f64 / self.control_reads.unwrap_or(1) as f64,
+            None => 1.0,
+        };
 
-            let normalized_count = (treatment_count - control_count).max(0.0);
-            bin_counts[i] = normalized_count.round() as usize;
-        }
+        info!(
+            "Computing local background (lambda_local) from {} with size_factor={}",
+            source_path, size_factor
+        );
 
-        Ok(())
+        crate::background_model::compute_lambda_local(source_path, bins, size_factor, &self.read_filter)
     }
 }
@@ -1,12 +1,65 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bio::stats::bayesian::model::{Likelihood, Model, Posterior, Prior};
 use bio::stats::{LogProb, Prob};
 use log::info;
-use statrs::distribution::{Discrete, NegativeBinomial};
+use statrs::distribution::{Discrete, NegativeBinomial, Poisson};
 use statrs::statistics::Statistics;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
 
 use crate::bam::GenomicRange;
 
+/// Capacity of the cache memoizing negative-binomial `ln_pmf` evaluations, keyed on
+/// quantized `(count, r, p)`. Genome-wide runs see the same low counts against the same
+/// constant background distribution millions of times, so this caps memory while still
+/// covering the common case.
+const PMF_CACHE_CAPACITY: usize = 4096;
+
+/// Key for the PMF memoization cache: observed count plus `r`/`p` quantized to thousandths.
+type PmfCacheKey = (usize, u64, u64);
+
+/// Capacity-bounded memoization cache backed by a `HashMap` for O(1) lookups and a `VecDeque`
+/// recording insertion order for eviction. Evicts in FIFO order rather than true LRU (a `get`
+/// never reorders `order`) so both `get` and `insert` stay O(1) instead of paying an O(capacity)
+/// scan per call on this genome-wide hot path; with a cache this size, FIFO catches the same
+/// repeated low counts against a constant background distribution that strict LRU would.
+struct FifoCache<K, V> {
+    capacity: usize,
+    map: HashMap<K, V>,
+    order: VecDeque<K>,
+}
+
+impl<K: Eq + std::hash::Hash + Clone, V: Clone> FifoCache<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            map: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if !self.map.contains_key(&key) {
+            if self.map.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.map.remove(&oldest);
+                }
+            }
+            self.order.push_back(key.clone());
+        }
+        self.map.insert(key, value);
+    }
+}
+
+fn quantize_param(value: f64) -> u64 {
+    (value * 1000.0).round() as u64
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct GenomicEvent {
     pub bin_count: usize,
@@ -16,65 +69,205 @@ pub struct GenomicEvent {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ReadCountData {
     pub observed_count: usize,
+    /// MACS-style `lambda_local` for this bin (see `background_model::compute_lambda_local`),
+    /// quantized to thousandths (e.g. `1234` means an expected rate of 1.234 reads) so the
+    /// struct stays `Eq + Hash`. Zero means the local rate fell below the floor.
+    pub expected_background_milli: u64,
+}
+
+/// Below this local background rate, the per-bin estimate is considered too thin to trust and
+/// the posterior falls back to the conservative global noise model.
+const MIN_BACKGROUND_FLOOR: f64 = 1.0;
+
+fn quantize_background(expected_background: f64) -> u64 {
+    if expected_background < MIN_BACKGROUND_FLOOR {
+        0
+    } else {
+        (expected_background * 1000.0).round() as u64
+    }
+}
+
+/// Method-of-moments fit of a negative binomial to a (possibly responsibility-weighted) mean and
+/// variance, clamped to the same reasonable bounds used throughout this module. Falls back to
+/// `fallback` when the component is degenerate (`variance <= mean`) or the fit isn't finite.
+fn fit_nb_moments(mean: f64, variance: f64, fallback: (f64, f64)) -> (f64, f64) {
+    if variance > mean && variance.is_finite() && mean > 0.0 {
+        let r = (mean * mean) / (variance - mean);
+        let p = mean / variance;
+
+        if r.is_finite() && r > 0.0 && p.is_finite() && p > 0.0 && p < 1.0 {
+            return (r.clamp(0.1, 100.0), p.clamp(0.05, 0.95));
+        }
+    }
+    fallback
+}
+
+fn weighted_mean_variance(counts: &[f64], weights: &[f64]) -> (f64, f64) {
+    let total_weight: f64 = weights.iter().sum();
+    if total_weight <= 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let mean = counts
+        .iter()
+        .zip(weights)
+        .map(|(count, weight)| count * weight)
+        .sum::<f64>()
+        / total_weight;
+
+    let variance = counts
+        .iter()
+        .zip(weights)
+        .map(|(count, weight)| weight * (count - mean).powi(2))
+        .sum::<f64>()
+        / total_weight;
+
+    (mean, variance)
+}
+
+/// Conservative fallback noise parameters, used before any data has been seen or when a
+/// component's fit is degenerate.
+const FALLBACK_NOISE: (f64, f64) = (1.0, 0.8);
+/// Conservative fallback signal parameters.
+const FALLBACK_SIGNAL: (f64, f64) = (2.0, 0.3);
+
+const EM_MAX_ITER: usize = 100;
+const EM_LOG_LIKELIHOOD_TOLERANCE: f64 = 1e-4;
+
+/// Fits a two-component negative-binomial mixture (`noise`, `signal`) to `counts` by EM,
+/// returning `(noise_r, noise_p, signal_r, signal_p, signal_weight)`. Initializes a low-mean
+/// noise component and a high-mean signal component by splitting at the median, then alternates
+/// E-steps (responsibilities proportional to `weight_k * NB_k.pmf(x)`) and M-steps
+/// (responsibility-weighted method-of-moments) until the log-likelihood change falls below
+/// `EM_LOG_LIKELIHOOD_TOLERANCE` or `EM_MAX_ITER` is reached.
+fn fit_nb_mixture(counts: &[f64]) -> (f64, f64, f64, f64, f64) {
+    if counts.len() < 2 {
+        return (FALLBACK_NOISE.0, FALLBACK_NOISE.1, FALLBACK_SIGNAL.0, FALLBACK_SIGNAL.1, 0.01);
+    }
+
+    let mut sorted = counts.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = sorted[sorted.len() / 2];
+
+    let (mut noise_r, mut noise_p) = fit_nb_moments(
+        counts.iter().copied().filter(|&c| c <= median).collect::<Vec<_>>().mean(),
+        counts.iter().copied().filter(|&c| c <= median).collect::<Vec<_>>().variance(),
+        FALLBACK_NOISE,
+    );
+    let (mut signal_r, mut signal_p) = fit_nb_moments(
+        counts.iter().copied().filter(|&c| c > median).collect::<Vec<_>>().mean(),
+        counts.iter().copied().filter(|&c| c > median).collect::<Vec<_>>().variance(),
+        FALLBACK_SIGNAL,
+    );
+    let mut weight_signal = (counts.iter().filter(|&&c| c > median).count() as f64
+        / counts.len() as f64)
+        .clamp(0.01, 0.99);
+
+    let mut prev_log_likelihood = f64::NEG_INFINITY;
+
+    for _ in 0..EM_MAX_ITER {
+        let noise_dist = match NegativeBinomial::new(noise_r, noise_p) {
+            Ok(dist) => dist,
+            Err(_) => break,
+        };
+        let signal_dist = match NegativeBinomial::new(signal_r, signal_p) {
+            Ok(dist) => dist,
+            Err(_) => break,
+        };
+
+        // E-step: responsibilities proportional to weight_k * NB_k.pmf(x), in log space.
+        let mut signal_responsibilities = Vec::with_capacity(counts.len());
+        let mut log_likelihood = 0.0;
+
+        for &count in counts {
+            let noise_joint = noise_dist.ln_pmf(count as u64) + (1.0 - weight_signal).ln();
+            let signal_joint = signal_dist.ln_pmf(count as u64) + weight_signal.ln();
+
+            let max_joint = noise_joint.max(signal_joint);
+            let evidence =
+                max_joint + ((noise_joint - max_joint).exp() + (signal_joint - max_joint).exp()).ln();
+
+            signal_responsibilities.push((signal_joint - evidence).exp());
+            log_likelihood += evidence;
+        }
+
+        // M-step: mixing weight as mean responsibility, components via weighted method of moments.
+        weight_signal = (signal_responsibilities.iter().sum::<f64>() / counts.len() as f64)
+            .clamp(0.01, 0.99);
+
+        let noise_responsibilities: Vec<f64> =
+            signal_responsibilities.iter().map(|r| 1.0 - r).collect();
+
+        let (signal_mean, signal_variance) = weighted_mean_variance(counts, &signal_responsibilities);
+        let (noise_mean, noise_variance) = weighted_mean_variance(counts, &noise_responsibilities);
+
+        (signal_r, signal_p) = fit_nb_moments(signal_mean, signal_variance, (signal_r, signal_p));
+        (noise_r, noise_p) = fit_nb_moments(noise_mean, noise_variance, FALLBACK_NOISE);
+
+        if (log_likelihood - prev_log_likelihood).abs() < EM_LOG_LIKELIHOOD_TOLERANCE {
+            prev_log_likelihood = log_likelihood;
+            break;
+        }
+        prev_log_likelihood = log_likelihood;
+    }
+
+    info!(
+        "EM mixture converged: noise=NB({}, {}), signal=NB({}, {}), weight_signal={}, log_likelihood={}",
+        noise_r, noise_p, signal_r, signal_p, weight_signal, prev_log_likelihood
+    );
+
+    (noise_r, noise_p, signal_r, signal_p, weight_signal)
 }
 
 #[allow(dead_code)]
 #[derive(Clone)]
 pub struct GenomicPrior {
-    pub r: f64, // number of successes parameter
-    pub p: f64, // success probability parameter
+    pub r: f64, // signal component's number-of-successes parameter
+    pub p: f64, // signal component's success-probability parameter
+    /// EM-fitted mixing weight for the signal component; also used as `P(signal)`.
+    pub weight: f64,
+    /// EM-fitted noise component parameters, used by `GenomicPosterior` in place of a fixed
+    /// background distribution.
+    pub noise_r: f64,
+    pub noise_p: f64,
 }
 
 impl Prior for GenomicPrior {
     type Event = GenomicEvent;
 
     fn compute(&self, _event: &Self::Event) -> LogProb {
-        let prior_signal = 0.01; // P(signal): Prior probability of true signal
-        LogProb::from(Prob(prior_signal))
+        LogProb::from(Prob(self.weight))
     }
 }
 
 impl GenomicPrior {
-    pub fn from_bin_counts(bin_counts: &[(GenomicRange, usize)]) -> Self {
-        let counts: Vec<f64> = bin_counts.iter().map(|(_, count)| *count as f64).collect();
+    pub fn from_bin_counts(bin_counts: &[(GenomicRange, usize, f64)]) -> Self {
+        let counts: Vec<f64> = bin_counts.iter().map(|(_, count, _)| *count as f64).collect();
 
-        if counts.is_empty() || counts.len() < 2 {
-            return Self { r: 2.0, p: 0.3 };
-        }
-
-        let mean = counts.clone().mean();
-        let variance = counts.clone().variance();
+        let (noise_r, noise_p, r, p, weight) = fit_nb_mixture(&counts);
 
         info!(
-            "Count statistics: mean={}, variance={}, n={}",
-            mean,
-            variance,
-            counts.len()
+            "Fitted NB mixture: signal r={}, p={}, noise r={}, p={}, weight_signal={}",
+            r, p, noise_r, noise_p, weight
         );
 
-        if variance > mean && variance.is_finite() && mean > 0.0 {
-            let r = (mean * mean) / (variance - mean);
-            let p = mean / variance;
-
-            if r.is_finite() && r > 0.0 && p.is_finite() && p > 0.0 && p < 1.0 {
-                let final_r = r.clamp(0.1, 100.0); // More reasonable bounds
-                let final_p = p.clamp(0.05, 0.95); // More reasonable bounds
-                info!("Using method of moments: r={}, p={}", final_r, final_p);
-                return Self {
-                    r: final_r,
-                    p: final_p,
-                };
-            }
+        Self {
+            r,
+            p,
+            weight,
+            noise_r,
+            noise_p,
         }
-
-        info!("Using conservative fallback parameters");
-        Self { r: 2.0, p: 0.3 }
     }
 }
 
 pub struct GenomicLikelihood {
     pub r: f64,
     pub p: f64,
+    /// Shared with the sibling `GenomicPosterior` and kept for the lifetime of the owning
+    /// `BayesianModel`, so repeated counts against the same signal distribution are only
+    /// evaluated once.
+    pmf_cache: Rc<RefCell<FifoCache<PmfCacheKey, f64>>>,
 }
 
 impl Likelihood for GenomicLikelihood {
@@ -82,6 +275,11 @@ impl Likelihood for GenomicLikelihood {
     type Data = ReadCountData;
 
     fn compute(&self, _event: &Self::Event, data: &Self::Data, _payload: &mut ()) -> LogProb {
+        let key = (data.observed_count, quantize_param(self.r), quantize_param(self.p));
+        if let Some(log_likelihood) = self.pmf_cache.borrow_mut().get(&key) {
+            return LogProb::from(log_likelihood);
+        }
+
         match NegativeBinomial::new(self.r, self.p) {
             Ok(nb_dist) => {
                 let log_likelihood = nb_dist.ln_pmf(data.observed_count as u64);
@@ -89,6 +287,7 @@ impl Likelihood for GenomicLikelihood {
                     "NB likelihood: count={}, r={}, p={}, ln_pmf={}",
                     data.observed_count, self.r, self.p, log_likelihood
                 );
+                self.pmf_cache.borrow_mut().insert(key, log_likelihood);
                 LogProb::from(log_likelihood)
             }
             Err(e) => {
@@ -102,7 +301,20 @@ impl Likelihood for GenomicLikelihood {
     }
 }
 
-pub struct GenomicPosterior;
+pub struct GenomicPosterior {
+    /// EM-fitted global noise component, used whenever a bin lacks a trustworthy local control
+    /// background. Kept alongside `background_dist` so cache keys can be formed without querying
+    /// the distribution for its parameters back.
+    noise_r: f64,
+    noise_p: f64,
+    /// Constructed once when the posterior is built (once per `identify_significant_bins` call)
+    /// rather than once per bin.
+    background_dist: NegativeBinomial,
+    /// EM-fitted mixing weight for the signal component; `1.0 - weight_signal` is used as the
+    /// noise prior.
+    weight_signal: f64,
+    pmf_cache: Rc<RefCell<FifoCache<PmfCacheKey, f64>>>,
+}
 
 impl Posterior for GenomicPosterior {
     type Event = GenomicEvent;
@@ -116,17 +328,40 @@ impl Posterior for GenomicPosterior {
         // Calculate signal likelihood using current parameters
         let signal_likelihood = joint_prob(event, data);
 
-        // Calculate noise likelihood using background parameters (conservative, high p)
-        let noise_likelihood = match NegativeBinomial::new(1.0, 0.8) {
-            Ok(nb_dist) => {
-                let log_likelihood = nb_dist.ln_pmf(data.observed_count as u64);
-                LogProb::from(log_likelihood)
+        // When the MACS-style lambda_local estimate is trustworthy, model noise as a Poisson
+        // centered on it; otherwise fall back to the EM-fitted global noise component, memoizing
+        // its PMF like the signal likelihood above.
+        //
+        // The noise likelihood here is the Poisson PMF at the observed count, P(X = k) with mean
+        // lambda_local. No upper-tail p-value P(X >= k) is computed anywhere, so `posterior_prob`
+        // reflects the local background only through this PMF term.
+        let noise_likelihood = if data.expected_background_milli > 0 {
+            let expected_background = data.expected_background_milli as f64 / 1000.0;
+            match Poisson::new(expected_background) {
+                Ok(pois) => LogProb::from(pois.ln_pmf(data.observed_count as u64)),
+                Err(_) => LogProb::ln_zero(),
             }
-            Err(_) => LogProb::ln_zero(),
+        } else {
+            let key = (
+                data.observed_count,
+                quantize_param(self.noise_r),
+                quantize_param(self.noise_p),
+            );
+            // Look up before matching so the borrow is released before the miss path inserts.
+            let cached = self.pmf_cache.borrow().get(&key);
+            let ln_pmf = match cached {
+                Some(cached) => cached,
+                None => {
+                    let value = self.background_dist.ln_pmf(data.observed_count as u64);
+                    self.pmf_cache.borrow_mut().insert(key, value);
+                    value
+                }
+            };
+            LogProb::from(ln_pmf)
         };
 
-        let prior_signal = LogProb::from(Prob(0.5));
-        let prior_noise = LogProb::from(Prob(0.5));
+        let prior_signal = LogProb::from(Prob(self.weight_signal));
+        let prior_noise = LogProb::from(Prob(1.0 - self.weight_signal));
 
         let joint_prob_signal = signal_likelihood + prior_signal;
         let joint_prob_noise = noise_likelihood + prior_noise;
@@ -145,20 +380,46 @@ impl Posterior for GenomicPosterior {
 pub struct BayesianModel {
     significance_threshold: f64,
     min_reads: u32,
+    fdr_alpha: Option<f64>,
     model: Model<GenomicLikelihood, GenomicPrior, GenomicPosterior>,
+    /// Memoizes negative-binomial PMF evaluations across the lifetime of this model, shared
+    /// into `GenomicLikelihood`/`GenomicPosterior` each time `identify_significant_bins` rebuilds
+    /// them.
+    pmf_cache: Rc<RefCell<FifoCache<PmfCacheKey, f64>>>,
 }
 
 impl BayesianModel {
-    pub fn new(significance_threshold: f64, min_reads: u32) -> Self {
-        let likelihood = GenomicLikelihood { r: 1.0, p: 0.5 };
-        let prior = GenomicPrior { r: 1.0, p: 0.5 };
-        let posterior = GenomicPosterior;
+    pub fn new(significance_threshold: f64, min_reads: u32, fdr_alpha: Option<f64>) -> Self {
+        let pmf_cache = Rc::new(RefCell::new(FifoCache::new(PMF_CACHE_CAPACITY)));
+
+        let likelihood = GenomicLikelihood {
+            r: 1.0,
+            p: 0.5,
+            pmf_cache: Rc::clone(&pmf_cache),
+        };
+        let prior = GenomicPrior {
+            r: FALLBACK_SIGNAL.0,
+            p: FALLBACK_SIGNAL.1,
+            weight: 0.01,
+            noise_r: FALLBACK_NOISE.0,
+            noise_p: FALLBACK_NOISE.1,
+        };
+        let posterior = GenomicPosterior {
+            noise_r: FALLBACK_NOISE.0,
+            noise_p: FALLBACK_NOISE.1,
+            background_dist: NegativeBinomial::new(FALLBACK_NOISE.0, FALLBACK_NOISE.1)
+                .expect("fallback noise parameters are always valid"),
+            weight_signal: 0.5,
+            pmf_cache: Rc::clone(&pmf_cache),
+        };
         let model = Model::new(likelihood, prior, posterior);
 
         Self {
             significance_threshold,
             min_reads,
+            fdr_alpha,
             model,
+            pmf_cache,
         }
     }
 
@@ -170,26 +431,46 @@ impl BayesianModel {
         self.min_reads
     }
 
+    pub fn fdr_alpha(&self) -> Option<f64> {
+        self.fdr_alpha
+    }
+
     pub fn identify_significant_bins(
         &mut self, // Note: needs to be mutable to update the model
-        bin_counts: &[(GenomicRange, usize)],
+        bin_counts: &[(GenomicRange, usize, f64)],
         total_reads: usize,
     ) -> Result<Vec<GenomicRange>> {
         info!("Applying rust-bio Bayesian model to identify significant bins");
 
         let prior = GenomicPrior::from_bin_counts(bin_counts);
         info!(
-            "Estimated negative binomial parameters: r={}, p={}",
-            prior.r, prior.p
+            "Estimated negative binomial parameters: r={}, p={}, weight={}, noise_r={}, noise_p={}",
+            prior.r, prior.p, prior.weight, prior.noise_r, prior.noise_p
         );
-        *self.model.prior_mut() = prior.clone();
 
-        self.model.likelihood_mut().r = prior.r;
-        self.model.likelihood_mut().p = prior.p;
+        // Rebuild the whole model rather than mutating in place: the posterior now carries the
+        // EM-fitted noise parameters and mixing weight too, and `Posterior` has no mutator
+        // accessor to match `prior_mut()`/`likelihood_mut()`. The background `NegativeBinomial`
+        // is constructed here, once per call, rather than once per bin.
+        let likelihood = GenomicLikelihood {
+            r: prior.r,
+            p: prior.p,
+            pmf_cache: Rc::clone(&self.pmf_cache),
+        };
+        let background_dist = NegativeBinomial::new(prior.noise_r, prior.noise_p)
+            .context("EM-fitted noise parameters are invalid for a negative binomial")?;
+        let posterior = GenomicPosterior {
+            noise_r: prior.noise_r,
+            noise_p: prior.noise_p,
+            background_dist,
+            weight_signal: prior.weight,
+            pmf_cache: Rc::clone(&self.pmf_cache),
+        };
+        self.model = Model::new(likelihood, prior.clone(), posterior);
 
         let mut posterior_probs = Vec::new();
 
-        for (bin, count) in bin_counts {
+        for (bin, count, expected_background) in bin_counts {
             if *count < self.min_reads as usize {
                 continue;
             }
@@ -201,6 +482,7 @@ impl BayesianModel {
 
             let data = ReadCountData {
                 observed_count: *count,
+                expected_background_milli: quantize_background(*expected_background),
             };
 
             let mut joint_prob_fn = |event: &GenomicEvent, data: &ReadCountData| -> LogProb {
@@ -222,8 +504,10 @@ impl BayesianModel {
             posterior_probs.push((bin.clone(), posterior_prob));
         }
 
-        let significant =
-            self.apply_posterior_threshold(posterior_probs, self.significance_threshold)?;
+        let significant = match self.fdr_alpha {
+            Some(alpha) => self.apply_fdr_control(posterior_probs, alpha)?,
+            None => self.apply_posterior_threshold(posterior_probs, self.significance_threshold)?,
+        };
 
         let significant_bins: Vec<GenomicRange> =
             significant.into_iter().map(|(bin, _)| bin).collect();
@@ -254,4 +538,48 @@ impl BayesianModel {
 
         Ok(significant)
     }
+
+    /// Selects the largest prefix of bins, sorted by descending posterior probability, whose
+    /// running expected FDR `(1/k) * sum_{i=1..k}(1 - posterior_i)` stays at or below `alpha`.
+    /// Each selected bin's `local_fdr` is set to the running expected FDR at its rank.
+    fn apply_fdr_control(
+        &self,
+        mut posterior_probs: Vec<(GenomicRange, f64)>,
+        alpha: f64,
+    ) -> Result<Vec<(GenomicRange, f64)>> {
+        posterior_probs.sort_by(|(bin_a, prob_a), (bin_b, prob_b)| {
+            prob_b
+                .partial_cmp(prob_a)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| bin_a.chrom.cmp(&bin_b.chrom))
+                .then_with(|| bin_a.start.cmp(&bin_b.start))
+        });
+
+        let mut significant = Vec::new();
+        let mut running_noise_sum = 0.0;
+
+        for (k, (bin, posterior_prob)) in posterior_probs.into_iter().enumerate() {
+            running_noise_sum += 1.0 - posterior_prob;
+            let expected_fdr = running_noise_sum / (k + 1) as f64;
+
+            if expected_fdr > alpha {
+                break;
+            }
+
+            let mut bin_clone = bin.clone();
+            bin_clone.posterior_prob = posterior_prob;
+            bin_clone.local_fdr = Some(expected_fdr);
+            info!(
+                "Bin {}:{}-{} accepted at rank {} with expected FDR {}",
+                bin_clone.chrom,
+                bin_clone.start,
+                bin_clone.end,
+                k + 1,
+                expected_fdr
+            );
+            significant.push((bin_clone, posterior_prob));
+        }
+
+        Ok(significant)
+    }
 }
@@ -0,0 +1,145 @@
+use anyhow::Result;
+use log::info;
+use statrs::distribution::{Discrete, NegativeBinomial};
+
+use crate::bam::GenomicRange;
+use crate::bayesian::GenomicPrior;
+
+/// The two hidden states for broad-domain segmentation: diffuse background vs. an enriched
+/// domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DomainState {
+    Background,
+    Enriched,
+}
+
+/// Segments a chromosome's ordered bin counts into broad enrichment domains using a two-state
+/// HMM (`Background`/`Enriched`, each with a negative-binomial emission) decoded with Viterbi,
+/// instead of gluing together individually significant bins within a fixed distance. Maximal
+/// runs of the `Enriched` state collapse into a single domain whose `posterior_prob` is the mean
+/// per-bin enriched posterior within the run.
+///
+/// The Viterbi DP below is hand-rolled rather than built on `bio::stats::hmm`: that module wants
+/// a `Model` constructed from fixed transition/emission probability matrices up front, not one
+/// whose emission probabilities come from two `NegativeBinomial`s (continuous, per-count PMFs
+/// refit per chromosome via `GenomicPrior`) evaluated lazily per bin. Shaping that into a static
+/// matrix would mean discretizing or precomputing over the count range instead of calling
+/// `ln_pmf` directly, so a small direct DP over the two states was simpler and avoided losing
+/// precision to a matrix approximation.
+pub fn segment_broad_domains(
+    bin_counts: &[(GenomicRange, usize, f64)],
+    prior: &GenomicPrior,
+    domain_stability: f64,
+) -> Result<Vec<GenomicRange>> {
+    if bin_counts.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let enriched_dist = NegativeBinomial::new(prior.r, prior.p)?;
+    let background_dist = NegativeBinomial::new(prior.noise_r, prior.noise_p)?;
+
+    // Self-transition probability controlling the expected domain length (in bins): a larger
+    // `domain_stability` (L) yields longer, less fragmented domains via self-transition 1 - 1/L.
+    let self_transition = 1.0 - (1.0 / domain_stability.max(1.0));
+    let switch_transition = 1.0 - self_transition;
+    let log_self = self_transition.ln();
+    let log_switch = switch_transition.ln();
+
+    let log_emission = |state: DomainState, count: usize| -> f64 {
+        match state {
+            DomainState::Background => background_dist.ln_pmf(count as u64),
+            DomainState::Enriched => enriched_dist.ln_pmf(count as u64),
+        }
+    };
+
+    let n = bin_counts.len();
+    let mut dp = vec![[f64::NEG_INFINITY; 2]; n];
+    let mut backpointer = vec![[0usize; 2]; n];
+
+    let initial_log = 0.5_f64.ln();
+    dp[0][DomainState::Background as usize] =
+        initial_log + log_emission(DomainState::Background, bin_counts[0].1);
+    dp[0][DomainState::Enriched as usize] =
+        initial_log + log_emission(DomainState::Enriched, bin_counts[0].1);
+
+    let states_by_idx = [DomainState::Background, DomainState::Enriched];
+
+    for i in 1..n {
+        for (to_idx, &to_state) in states_by_idx.iter().enumerate() {
+            let stay_score = dp[i - 1][to_idx] + log_self;
+            let switch_score = dp[i - 1][1 - to_idx] + log_switch;
+
+            let (best_score, best_from) = if stay_score >= switch_score {
+                (stay_score, to_idx)
+            } else {
+                (switch_score, 1 - to_idx)
+            };
+
+            dp[i][to_idx] = best_score + log_emission(to_state, bin_counts[i].1);
+            backpointer[i][to_idx] = best_from;
+        }
+    }
+
+    let mut state_idx = if dp[n - 1][1] > dp[n - 1][0] { 1 } else { 0 };
+    let mut state_path = vec![0usize; n];
+    state_path[n - 1] = state_idx;
+    for i in (1..n).rev() {
+        state_idx = backpointer[i][state_idx];
+        state_path[i - 1] = state_idx;
+    }
+
+    // Collapse maximal runs of the Enriched state into domains.
+    let mut domains = Vec::new();
+    let mut run_start: Option<usize> = None;
+
+    for i in 0..=n {
+        let is_enriched = i < n && state_path[i] == DomainState::Enriched as usize;
+        match (run_start, is_enriched) {
+            (None, true) => run_start = Some(i),
+            (Some(start), false) => {
+                let mean_posterior = (start..i)
+                    .map(|j| {
+                        enriched_posterior(bin_counts[j].1, &enriched_dist, &background_dist, prior.weight)
+                    })
+                    .sum::<f64>()
+                    / (i - start) as f64;
+
+                domains.push(GenomicRange {
+                    chrom: bin_counts[start].0.chrom.clone(),
+                    start: bin_counts[start].0.start,
+                    end: bin_counts[i - 1].0.end,
+                    posterior_prob: mean_posterior,
+                    local_fdr: None,
+                });
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    info!(
+        "HMM broad-domain segmentation produced {} domains from {} bins",
+        domains.len(),
+        n
+    );
+
+    Ok(domains)
+}
+
+/// Posterior probability of the `Enriched` state for a single bin, mirroring the EM-fitted
+/// signal/noise split used by `GenomicPosterior`.
+fn enriched_posterior(
+    count: usize,
+    enriched_dist: &NegativeBinomial,
+    background_dist: &NegativeBinomial,
+    weight_signal: f64,
+) -> f64 {
+    let signal_joint = enriched_dist.ln_pmf(count as u64) + weight_signal.ln();
+    let noise_joint = background_dist.ln_pmf(count as u64) + (1.0 - weight_signal).ln();
+
+    let max_joint = signal_joint.max(noise_joint);
+    let evidence =
+        max_joint + ((signal_joint - max_joint).exp() + (noise_joint - max_joint).exp()).ln();
+
+    (signal_joint - evidence).exp().clamp(0.0, 1.0)
+}
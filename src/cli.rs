@@ -1,4 +1,27 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+
+/// Output formats supported by `Peaks::write_to_stdout`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// BED6 with the posterior probability as the score, plus the local FDR as a seventh column
+    /// when computed.
+    Bed,
+    /// 10-column ENCODE narrowPeak.
+    Narrowpeak,
+    /// Continuous per-bin signal track.
+    Bedgraph,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OutputFormat::Bed => "bed",
+            OutputFormat::Narrowpeak => "narrowpeak",
+            OutputFormat::Bedgraph => "bedgraph",
+        };
+        write!(f, "{}", name)
+    }
+}
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -22,6 +45,33 @@ pub struct Cli {
     #[arg(short = 'p', long, default_value_t = 0.95, help = "Posterior probability threshold")]
     pub posterior_threshold: f64,
 
+    #[arg(
+        long,
+        help = "Control the expected Bayesian FDR instead of a fixed posterior threshold"
+    )]
+    pub fdr: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Indexed reference FASTA used to reweight fragment counts for GC-content bias"
+    )]
+    pub reference: Option<String>,
+
+    #[arg(long, default_value_t = 0, help = "Minimum mapping quality to count a read")]
+    pub min_mapq: u8,
+
+    #[arg(
+        long,
+        help = "Count PCR/optical duplicate-flagged reads instead of skipping them"
+    )]
+    pub keep_duplicates: bool,
+
+    #[arg(
+        long,
+        help = "Extend single-end (non-properly-paired) reads to this fragment length before assigning a bin; properly-paired reads always use the pos/insert_size midpoint"
+    )]
+    pub extend_reads: Option<u32>,
+
     #[arg(short = 't', long, default_value_t = 200)]
     pub step: u32,
 
@@ -31,11 +81,47 @@ pub struct Cli {
     #[arg(long)]
     pub broad: bool,
 
+    #[arg(
+        long,
+        default_value_t = 50.0,
+        help = "Expected broad-domain length in bins; sets the HMM self-transition to 1 - 1/L"
+    )]
+    pub domain_stability: f64,
+
     #[arg(long)]
     pub verbose: bool,
 
     #[arg(long, help = "Write metrics to a file (e.g., <prefix>_sbpc.json)")]
     pub metrics_file: bool,
+
+    #[arg(
+        long,
+        help = "Instead of whole-genome peak calling, extract a reproducible debug fixture for chrom:start-end (requires --testcase-dir)"
+    )]
+    pub testcase: Option<String>,
+
+    #[arg(long, help = "Output directory for --testcase fixtures")]
+    pub testcase_dir: Option<String>,
+
+    #[arg(
+        long,
+        help = "BED file of regions to exclude (e.g. an ENCODE blacklist); bins overlapping any of them are dropped"
+    )]
+    pub blacklist: Option<String>,
+
+    #[arg(
+        long,
+        help = "BED file of regions to restrict binning to (e.g. a targeted/captured panel); bins outside all of them are dropped"
+    )]
+    pub regions: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OutputFormat::Bed,
+        help = "Output format: bed, narrowpeak, or bedgraph"
+    )]
+    pub output_format: OutputFormat,
 }
 
 impl Cli {
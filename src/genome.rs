@@ -6,13 +6,29 @@ use std::io::{BufRead, BufReader};
 use std::path::Path;
 
 use crate::bam::GenomicRange;
+use crate::intervals::IntervalIndex;
 
 pub struct Genome {
     pub seqnames: Vec<String>,
     pub lengths: Vec<u32>,
+    blacklist: Option<IntervalIndex>,
+    whitelist: Option<IntervalIndex>,
 }
 
 impl Genome {
+    /// Builds a `Genome` directly from parallel chromosome name/length vectors, with no
+    /// blacklist/whitelist region filters applied. Used by callers (and tests) that already have
+    /// `seqnames`/`lengths` in hand rather than a chromosome-sizes file or BAM header; use
+    /// [`Self::with_region_filters`] afterward to add `--blacklist`/`--regions` filtering.
+    pub fn new(seqnames: Vec<String>, lengths: Vec<u32>) -> Self {
+        Self {
+            seqnames,
+            lengths,
+            blacklist: None,
+            whitelist: None,
+        }
+    }
+
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         info!("Loading genome from file: {:?}", path.as_ref());
 
@@ -41,7 +57,7 @@ impl Genome {
             anyhow::bail!("No chromosomes found in the file");
         }
 
-        Ok(Self { seqnames, lengths })
+        Ok(Self::new(seqnames, lengths))
     }
 
     pub fn from_bam<P: AsRef<Path>>(path: P) -> Result<Self> {
@@ -94,10 +110,39 @@ impl Genome {
             }
         }
 
-        Self {
-            seqnames: filtered_seqnames,
-            lengths: filtered_lengths,
+        Self::new(filtered_seqnames, filtered_lengths)
+    }
+
+    /// Restrict binning to `regions_path` (a whitelist, e.g. a captured/targeted panel) and/or
+    /// drop bins overlapping `blacklist_path` (e.g. an ENCODE blacklist). Either may be omitted.
+    pub fn with_region_filters(
+        mut self,
+        blacklist_path: Option<&str>,
+        regions_path: Option<&str>,
+    ) -> Result<Self> {
+        if let Some(path) = blacklist_path {
+            self.blacklist = Some(IntervalIndex::from_bed(path)?);
+        }
+        if let Some(path) = regions_path {
+            self.whitelist = Some(IntervalIndex::from_bed(path)?);
+        }
+        Ok(self)
+    }
+
+    /// Whether a bin should be kept: not overlapping the blacklist, and (when a whitelist is set)
+    /// overlapping at least one whitelisted region.
+    fn passes_region_filters(&self, chrom: &str, start: u32, end: u32) -> bool {
+        if let Some(blacklist) = &self.blacklist {
+            if blacklist.overlaps(chrom, start, end) {
+                return false;
+            }
+        }
+        if let Some(whitelist) = &self.whitelist {
+            if !whitelist.overlaps(chrom, start, end) {
+                return false;
+            }
         }
+        true
     }
 
     pub fn create_bins(&self, step: u32, chrom: Option<&str>) -> Result<Vec<GenomicRange>> {
@@ -111,12 +156,15 @@ impl Genome {
 
                     while start + step <= length {
                         let end = start + step;
-                        bins.push(GenomicRange {
-                            chrom: chromosome.to_string(),
-                            start,
-                            end,
-                            posterior_prob: 0.0, // Will be set by Bayesian model
-                        });
+                        if self.passes_region_filters(chromosome, start, end) {
+                            bins.push(GenomicRange {
+                                chrom: chromosome.to_string(),
+                                start,
+                                end,
+                                posterior_prob: 0.0, // Will be set by Bayesian model
+                                local_fdr: None,
+                            });
+                        }
                         start += step;
                     }
 
@@ -140,18 +188,21 @@ impl Genome {
 
                     while start + step <= length {
                         let end = start + step;
-                        bins.push(GenomicRange {
-                            chrom: chrom.clone(),
-                            start,
-                            end,
-                            posterior_prob: 0.0, // Will be set by Bayesian model
-                        });
+                        if self.passes_region_filters(chrom, start, end) {
+                            bins.push(GenomicRange {
+                                chrom: chrom.clone(),
+                                start,
+                                end,
+                                posterior_prob: 0.0, // Will be set by Bayesian model
+                                local_fdr: None,
+                            });
+                        }
                         start += step;
                     }
 
                     let chrom_bins_count = bins.len() - chrom_bins_start;
                     if chrom_bins_count > 0 {
-                        info!("Created {} non-overlapping genomic bins with step={} for chromosome {}", 
+                        info!("Created {} non-overlapping genomic bins with step={} for chromosome {}",
                               chrom_bins_count, step, chrom);
                     }
                 }
@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use bio::io::bed;
+use log::info;
+use std::collections::HashMap;
+
+/// Per-chromosome overlap index for a set of BED intervals (an ENCODE-style blacklist, or a
+/// whitelist of targeted/captured regions). Intervals are sorted by start, alongside a running
+/// max-end so a query can test for overlap with a single binary search instead of scanning every
+/// interval on the chromosome.
+pub struct IntervalIndex {
+    by_chrom: HashMap<String, ChromIntervals>,
+}
+
+struct ChromIntervals {
+    starts: Vec<u32>,
+    /// `prefix_max_end[i] = max(end of intervals[0..=i])`, so "does any of the first `k` intervals
+    /// (sorted by start) overlap the query" reduces to a single comparison once `k` is known.
+    prefix_max_end: Vec<u32>,
+}
+
+impl IntervalIndex {
+    pub fn from_bed(path: &str) -> Result<Self> {
+        info!("Loading interval index from BED file: {}", path);
+
+        let mut reader =
+            bed::Reader::from_file(path).context(format!("Failed to open BED file: {}", path))?;
+
+        let mut by_chrom: HashMap<String, Vec<(u32, u32)>> = HashMap::new();
+        for record in reader.records() {
+            let record = record.context(format!("Failed to parse BED record in {}", path))?;
+            by_chrom
+                .entry(record.chrom().to_string())
+                .or_default()
+                .push((record.start() as u32, record.end() as u32));
+        }
+
+        let by_chrom = by_chrom
+            .into_iter()
+            .map(|(chrom, mut intervals)| {
+                intervals.sort_by_key(|&(start, _)| start);
+
+                let mut starts = Vec::with_capacity(intervals.len());
+                let mut prefix_max_end = Vec::with_capacity(intervals.len());
+                let mut running_max = 0u32;
+                for (start, end) in intervals {
+                    running_max = running_max.max(end);
+                    starts.push(start);
+                    prefix_max_end.push(running_max);
+                }
+
+                (
+                    chrom,
+                    ChromIntervals {
+                        starts,
+                        prefix_max_end,
+                    },
+                )
+            })
+            .collect();
+
+        Ok(Self { by_chrom })
+    }
+
+    /// Whether `[start, end)` on `chrom` overlaps any indexed interval.
+    pub fn overlaps(&self, chrom: &str, start: u32, end: u32) -> bool {
+        let Some(intervals) = self.by_chrom.get(chrom) else {
+            return false;
+        };
+
+        // All intervals with `interval.start < end` precede this index (intervals are sorted by
+        // start); among those, an overlap exists iff the largest `end` seen so far is > `start`.
+        let candidate_count = intervals.starts.partition_point(|&s| s < end);
+        if candidate_count == 0 {
+            return false;
+        }
+
+        intervals.prefix_max_end[candidate_count - 1] > start
+    }
+}
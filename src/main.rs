@@ -2,12 +2,16 @@ use clap::Parser;
 use log::info;
 use std::time::Instant;
 
+mod background_model;
 mod bam;
 mod bayesian;
+mod broad_domains;
 mod cli;
 mod genome;
+mod intervals;
 mod metrics;
 mod peak_caller;
+mod testcase;
 
 fn main() -> anyhow::Result<()> {
     // Set RUST_LOG if --verbose is passed, before env_logger::init()
@@ -20,13 +24,21 @@ fn main() -> anyhow::Result<()> {
     }
     env_logger::init();
 
+    if let Some(region) = &cli.testcase {
+        let testcase_dir = cli
+            .testcase_dir
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("--testcase requires --testcase-dir"))?;
+        return testcase::generate_testcase(&cli, region, testcase_dir);
+    }
+
     let start_time = Instant::now();
 
     let mut peak_caller = peak_caller::PeakCaller::new(&cli)?;
 
     let peaks = peak_caller.call_peaks()?;
 
-    let peak_count = peaks.write_to_stdout_bed();
+    let peak_count = peaks.write_to_stdout(cli.output_format);
 
     let metrics = metrics::Metrics::new(
         env!("CARGO_PKG_VERSION"),
@@ -1,6 +1,7 @@
-use crate::bam::{BamProcessor, GenomicRange};
-use crate::bayesian::BayesianModel;
-use crate::cli::Cli;
+use crate::bam::{BamProcessor, GenomicRange, ReadFilter};
+use crate::bayesian::{BayesianModel, GenomicPrior};
+use crate::broad_domains;
+use crate::cli::{Cli, OutputFormat};
 use crate::genome::Genome;
 use anyhow::Result;
 use bio::io::bed::{Record, Writer};
@@ -8,7 +9,7 @@ use bio::io::bed::{Record, Writer};
 use log::info;
 use rayon::prelude::*;
 use std::collections::HashMap;
-use std::io;
+use std::io::{self, Write};
 
 pub struct PeakCaller {
     cli: Cli,
@@ -27,10 +28,24 @@ impl PeakCaller {
         } else {
             Genome::from_bam(&cli.bam)?
         };
+        let genome =
+            genome.with_region_filters(cli.blacklist.as_deref(), cli.regions.as_deref())?;
+
+        let read_filter = ReadFilter {
+            min_mapq: cli.min_mapq,
+            skip_duplicates: !cli.keep_duplicates,
+            extend_to: cli.extend_reads,
+            ..ReadFilter::default()
+        };
 
-        let bam_processor = BamProcessor::new(&cli.bam, cli.control.as_deref())?;
+        let bam_processor = BamProcessor::new(
+            &cli.bam,
+            cli.control.as_deref(),
+            cli.reference.as_deref(),
+            read_filter,
+        )?;
 
-        let bayesian_model = BayesianModel::new(cli.posterior_threshold, cli.minreads);
+        let bayesian_model = BayesianModel::new(cli.posterior_threshold, cli.minreads, cli.fdr);
 
         Ok(Self {
             cli: cli_copy,
@@ -55,40 +70,81 @@ impl PeakCaller {
         let model_params = (
             self.bayesian_model.significance_threshold(),
             self.bayesian_model.min_reads(),
+            self.bayesian_model.fdr_alpha(),
         );
 
-        let all_peaks: Vec<GenomicRange> = chroms
-            .par_iter()
-            .flat_map(|chrom| {
-                // Create bins for this chromosome only
-                let bins: Vec<GenomicRange> = self.genome.create_bins(step, Some(chrom)).unwrap();
+        let (chrom_peaks, chrom_signal): (Vec<Vec<GenomicRange>>, Vec<Vec<(GenomicRange, f64)>>) =
+            chroms
+                .par_iter()
+                .map(|chrom| {
+                    // Create bins for this chromosome only
+                    let bins: Vec<GenomicRange> =
+                        self.genome.create_bins(step, Some(chrom)).unwrap();
 
-                if bins.is_empty() {
-                    return Vec::new();
-                }
+                    if bins.is_empty() {
+                        return (Vec::new(), Vec::new());
+                    }
 
-                // Count reads in bins for this chromosome
-                let bin_counts = bam_processor.count_reads_in_bins(&bins).unwrap();
+                    // Count reads in bins for this chromosome; GC-content bias correction (when
+                    // `--reference` is given) already happened per-fragment inside `BamProcessor`.
+                    let bin_counts = bam_processor.count_reads_in_bins(&bins).unwrap();
+
+                    // CPM-normalized per-bin signal, retained for `--output-format bedgraph` and
+                    // for picking each peak's summit in narrowPeak output.
+                    let signal: Vec<(GenomicRange, f64)> = bin_counts
+                        .iter()
+                        .map(|(bin, count, _)| {
+                            let normalized = if total_reads > 0 {
+                                *count as f64 / total_reads as f64 * 1_000_000.0
+                            } else {
+                                0.0
+                            };
+                            (bin.clone(), normalized)
+                        })
+                        .collect();
+
+                    if self.cli.broad {
+                        // Segment the ordered bin counts into broad domains with a two-state HMM
+                        // instead of distance-merging individually significant bins.
+                        let prior = GenomicPrior::from_bin_counts(&bin_counts);
+                        let domains = broad_domains::segment_broad_domains(
+                            &bin_counts,
+                            &prior,
+                            self.cli.domain_stability,
+                        )
+                        .unwrap();
+
+                        let peaks = self.filter_peaks_by_width(domains, minwidth).unwrap();
+                        return (peaks, signal);
+                    }
 
-                // Create a thread-local model instance
-                let mut thread_local_model = BayesianModel::new(model_params.0, model_params.1);
+                    // Create a thread-local model instance
+                    let mut thread_local_model =
+                        BayesianModel::new(model_params.0, model_params.1, model_params.2);
 
-                // Identify significant bins using the thread-local model
-                let significant_bins = thread_local_model
-                    .identify_significant_bins(&bin_counts, total_reads)
-                    .unwrap();
+                    // Identify significant bins using the thread-local model
+                    let significant_bins = thread_local_model
+                        .identify_significant_bins(&bin_counts, total_reads)
+                        .unwrap();
 
-                // Merge bins into peaks for this chromosome
-                let merged_peaks = self.merge_bins_into_peaks(significant_bins, mdist).unwrap();
+                    // Merge bins into peaks for this chromosome
+                    let merged_peaks = self.merge_bins_into_peaks(significant_bins, mdist).unwrap();
 
-                // Filter peaks by width and return directly
-                self.filter_peaks_by_width(merged_peaks, minwidth).unwrap()
-            })
-            .collect();
+                    // Filter peaks by width and return directly
+                    let peaks = self.filter_peaks_by_width(merged_peaks, minwidth).unwrap();
+                    (peaks, signal)
+                })
+                .unzip();
+
+        let all_peaks: Vec<GenomicRange> = chrom_peaks.into_iter().flatten().collect();
+        let signal_track: Vec<(GenomicRange, f64)> = chrom_signal.into_iter().flatten().collect();
 
         info!("Peak calling completed, found {} peaks", all_peaks.len());
 
-        Ok(Peaks { ranges: all_peaks })
+        Ok(Peaks {
+            ranges: all_peaks,
+            signal_track,
+        })
     }
 
     fn merge_bins_into_peaks(
@@ -150,9 +206,21 @@ impl PeakCaller {
 
 pub struct Peaks {
     pub ranges: Vec<GenomicRange>,
+    /// Every bin's CPM-normalized count, kept alongside the final (merged, width-filtered) peaks
+    /// so `--output-format bedgraph` can emit a continuous genome-wide track and narrowPeak output
+    /// can report each peak's summit.
+    pub signal_track: Vec<(GenomicRange, f64)>,
 }
 
 impl Peaks {
+    pub fn write_to_stdout(&self, format: OutputFormat) -> usize {
+        match format {
+            OutputFormat::Bed => self.write_to_stdout_bed(),
+            OutputFormat::Narrowpeak => self.write_to_stdout_narrowpeak(),
+            OutputFormat::Bedgraph => self.write_to_stdout_bedgraph(),
+        }
+    }
+
     pub fn write_to_stdout_bed(&self) -> usize {
         let stdout = io::stdout();
         let handle = stdout.lock();
@@ -166,9 +234,100 @@ impl Peaks {
             record.set_name(&format!("peak{}", i + 1));
             record.set_score(&format!("{:.6}", range.posterior_prob));
             record.push_aux("."); // Add strand field as "." (unknown)
+            if let Some(local_fdr) = range.local_fdr {
+                record.push_aux(&format!("{:.6}", local_fdr));
+            }
 
             writer.write(&record).unwrap();
         }
         self.ranges.len()
     }
+
+    /// 10-column ENCODE narrowPeak: chrom, start, end, name, score (0-1000, scaled from the
+    /// posterior), strand (always `.`), signalValue (CPM-normalized summit count), pValue and
+    /// qValue (`-log10`, capped rather than allowed to reach infinity), and the summit offset
+    /// from `start`. `qValue` is `-1` when `--fdr` wasn't used, matching the ENCODE convention
+    /// for an unavailable field.
+    pub fn write_to_stdout_narrowpeak(&self) -> usize {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        self.write_narrowpeak(&mut handle).unwrap()
+    }
+
+    /// Writes the narrowPeak body to `writer`, split out from [`Self::write_to_stdout_narrowpeak`]
+    /// so the format can be asserted against an in-memory buffer in tests without touching stdout.
+    pub fn write_narrowpeak<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+        for (i, range) in self.ranges.iter().enumerate() {
+            let score = (range.posterior_prob * 1000.0).round().clamp(0.0, 1000.0) as u32;
+            let (signal_value, summit_offset) = self.peak_summit(range);
+            let p_value = neg_log10(1.0 - range.posterior_prob);
+            let q_value = match range.local_fdr {
+                Some(local_fdr) => neg_log10(local_fdr),
+                None => -1.0,
+            };
+
+            writeln!(
+                writer,
+                "{}\t{}\t{}\tpeak{}\t{}\t.\t{:.6}\t{:.6}\t{:.6}\t{}",
+                range.chrom,
+                range.start,
+                range.end,
+                i + 1,
+                score,
+                signal_value,
+                p_value,
+                q_value,
+                summit_offset
+            )?;
+        }
+        Ok(self.ranges.len())
+    }
+
+    /// Continuous per-bin bedGraph signal track (CPM-normalized counts) across every bin that was
+    /// scored, suitable for loading into a genome browser or converting to bigWig.
+    pub fn write_to_stdout_bedgraph(&self) -> usize {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        self.write_bedgraph(&mut handle).unwrap()
+    }
+
+    /// Writes the bedGraph body to `writer`, split out from [`Self::write_to_stdout_bedgraph`] so
+    /// the format can be asserted against an in-memory buffer in tests without touching stdout.
+    pub fn write_bedgraph<W: Write>(&self, writer: &mut W) -> io::Result<usize> {
+        writeln!(writer, "track type=bedGraph name=\"sbpc signal\"")?;
+
+        let mut sorted_track = self.signal_track.clone();
+        sorted_track.sort_by(|(bin_a, _), (bin_b, _)| {
+            bin_a.chrom.cmp(&bin_b.chrom).then(bin_a.start.cmp(&bin_b.start))
+        });
+
+        for (bin, signal) in &sorted_track {
+            writeln!(writer, "{}\t{}\t{}\t{:.6}", bin.chrom, bin.start, bin.end, signal)?;
+        }
+        Ok(sorted_track.len())
+    }
+
+    /// The highest-signal bin overlapping `range`, returned as `(signal_value, offset_from_start)`
+    /// for narrowPeak's signalValue and summit columns.
+    fn peak_summit(&self, range: &GenomicRange) -> (f64, i64) {
+        let mut best_signal = 0.0;
+        let mut best_offset = (range.end.saturating_sub(range.start) / 2) as i64;
+
+        for (bin, signal) in &self.signal_track {
+            if bin.chrom == range.chrom && bin.start < range.end && bin.end > range.start {
+                if *signal > best_signal {
+                    best_signal = *signal;
+                    let midpoint = (bin.start + bin.end) / 2;
+                    best_offset = midpoint as i64 - range.start as i64;
+                }
+            }
+        }
+
+        (best_signal, best_offset)
+    }
+}
+
+/// `-log10(p)`, capping `p` away from zero so the result stays finite.
+fn neg_log10(p: f64) -> f64 {
+    -p.max(1e-300).log10()
 }
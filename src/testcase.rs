@@ -0,0 +1,229 @@
+use anyhow::{Context, Result};
+use log::info;
+use rust_htslib::bam::{self, IndexedReader, Read};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bam::{BamProcessor, GenomicRange, ReadFilter};
+use crate::bayesian::BayesianModel;
+use crate::cli::Cli;
+use crate::genome::Genome;
+
+/// A single genomic region parsed from `--testcase chrom:start-end`.
+#[derive(Debug, Clone)]
+pub struct TestcaseRegion {
+    pub chrom: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl TestcaseRegion {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (chrom, range) = spec.split_once(':').context(format!(
+            "Invalid --testcase region '{}': expected chrom:start-end",
+            spec
+        ))?;
+        let (start, end) = range.split_once('-').context(format!(
+            "Invalid --testcase region '{}': expected chrom:start-end",
+            spec
+        ))?;
+
+        Ok(Self {
+            chrom: chrom.to_string(),
+            start: start
+                .parse()
+                .context(format!("Invalid testcase start: {}", start))?,
+            end: end
+                .parse()
+                .context(format!("Invalid testcase end: {}", end))?,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct BinRecord {
+    chrom: String,
+    start: u32,
+    end: u32,
+    count: usize,
+    expected_background: f64,
+}
+
+#[derive(Serialize)]
+struct PeakCall {
+    chrom: String,
+    start: u32,
+    end: u32,
+    posterior_prob: f64,
+    local_fdr: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct TestcaseManifest {
+    invocation: String,
+    region: String,
+    total_reads: usize,
+    control_reads: Option<usize>,
+    scale_factor: f64,
+    bins: Vec<BinRecord>,
+    significant_bins: Vec<PeakCall>,
+}
+
+/// Extracts a small, self-contained fixture for `region_spec`: records overlapping the region
+/// from the treatment (and control, if any) BAMs into sorted+indexed BAMs under `testcase_dir`,
+/// plus a `manifest.json` recording the bins, raw/control counts, scale factor, the resulting
+/// peak calls, and the exact CLI invocation, so a bug report can be replayed deterministically.
+pub fn generate_testcase(cli: &Cli, region_spec: &str, testcase_dir: &str) -> Result<()> {
+    let region = TestcaseRegion::parse(region_spec)?;
+    info!(
+        "Generating testcase fixture for {}:{}-{} in {}",
+        region.chrom, region.start, region.end, testcase_dir
+    );
+
+    fs::create_dir_all(testcase_dir).context(format!(
+        "Failed to create testcase directory: {}",
+        testcase_dir
+    ))?;
+
+    extract_region(
+        &cli.bam,
+        &region,
+        Path::new(testcase_dir).join("treatment.bam"),
+    )?;
+    if let Some(control) = &cli.control {
+        extract_region(control, &region, Path::new(testcase_dir).join("control.bam"))?;
+    }
+
+    let read_filter = ReadFilter {
+        min_mapq: cli.min_mapq,
+        skip_duplicates: !cli.keep_duplicates,
+        extend_to: cli.extend_reads,
+        ..ReadFilter::default()
+    };
+
+    let bam_processor = BamProcessor::new(
+        &cli.bam,
+        cli.control.as_deref(),
+        cli.reference.as_deref(),
+        read_filter,
+    )?;
+
+    let genome = Genome::from_bam(&cli.bam)?
+        .with_region_filters(cli.blacklist.as_deref(), cli.regions.as_deref())?;
+    let bins: Vec<GenomicRange> = genome
+        .create_bins(cli.step, Some(&region.chrom))?
+        .into_iter()
+        .filter(|bin| bin.start < region.end && bin.end > region.start)
+        .collect();
+
+    let bin_counts = bam_processor.count_reads_in_bins(&bins)?;
+
+    let mut model = BayesianModel::new(cli.posterior_threshold, cli.minreads, cli.fdr);
+    let significant = model.identify_significant_bins(&bin_counts, bam_processor.total_reads())?;
+
+    let scale_factor = match bam_processor.control_reads() {
+        Some(control_reads) if control_reads > 0 => {
+            bam_processor.total_reads() as f64 / control_reads as f64
+        }
+        _ => 1.0,
+    };
+
+    let bin_records: Vec<BinRecord> = bin_counts
+        .iter()
+        .map(|(bin, count, expected_background)| BinRecord {
+            chrom: bin.chrom.clone(),
+            start: bin.start,
+            end: bin.end,
+            count: *count,
+            expected_background: *expected_background,
+        })
+        .collect();
+
+    let significant_bins: Vec<PeakCall> = significant
+        .iter()
+        .map(|bin| PeakCall {
+            chrom: bin.chrom.clone(),
+            start: bin.start,
+            end: bin.end,
+            posterior_prob: bin.posterior_prob,
+            local_fdr: bin.local_fdr,
+        })
+        .collect();
+
+    let manifest = TestcaseManifest {
+        invocation: std::env::args().collect::<Vec<String>>().join(" "),
+        region: format!("{}:{}-{}", region.chrom, region.start, region.end),
+        total_reads: bam_processor.total_reads(),
+        control_reads: bam_processor.control_reads(),
+        scale_factor,
+        bins: bin_records,
+        significant_bins,
+    };
+
+    let manifest_path = Path::new(testcase_dir).join("manifest.json");
+    let json = serde_json::to_string_pretty(&manifest)?;
+    fs::write(&manifest_path, json)
+        .context(format!("Failed to write testcase manifest: {:?}", manifest_path))?;
+
+    info!(
+        "Testcase fixture written to {} ({} bins, {} significant)",
+        testcase_dir,
+        manifest.bins.len(),
+        manifest.significant_bins.len()
+    );
+
+    Ok(())
+}
+
+/// Pulls the records overlapping `region` out of `source_path` into a new sorted+indexed BAM at
+/// `dest_path`, via `IndexedReader::fetch` (which already yields records in the source's
+/// coordinate-sorted order).
+fn extract_region(source_path: &str, region: &TestcaseRegion, dest_path: PathBuf) -> Result<()> {
+    let mut reader = IndexedReader::from_path(source_path)
+        .context(format!("Failed to open indexed BAM: {}", source_path))?;
+    let header_view = reader.header().to_owned();
+
+    let tid = header_view
+        .target_names()
+        .iter()
+        .position(|name| std::str::from_utf8(name).unwrap_or("") == region.chrom)
+        .context(format!(
+            "Chromosome {} not found in {}",
+            region.chrom, source_path
+        ))?;
+
+    reader
+        .fetch((tid as u32, region.start, region.end))
+        .context(format!(
+            "Failed to fetch region {}:{}-{}",
+            region.chrom, region.start, region.end
+        ))?;
+
+    let header = bam::Header::from_template(&header_view);
+    let mut writer = bam::Writer::from_path(&dest_path, &header, bam::Format::Bam)
+        .context(format!("Failed to create testcase BAM: {:?}", dest_path))?;
+
+    let mut record_count = 0usize;
+    let mut seen_qnames = HashSet::new();
+    for record in reader.records() {
+        let record = record?;
+        seen_qnames.insert(record.qname().to_vec());
+        writer.write(&record)?;
+        record_count += 1;
+    }
+    drop(writer);
+
+    bam::index::build(&dest_path, None, bam::index::Type::Bai, 1)
+        .context(format!("Failed to index testcase BAM: {:?}", dest_path))?;
+
+    info!(
+        "Extracted {} records ({} distinct reads) to {:?}",
+        record_count,
+        seen_qnames.len(),
+        dest_path
+    );
+
+    Ok(())
+}
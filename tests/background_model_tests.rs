@@ -0,0 +1,60 @@
+use sbpc::bam::GenomicRange;
+use sbpc::background_model::lambda_for_bin;
+
+fn bin(start: u32, end: u32) -> GenomicRange {
+    GenomicRange {
+        chrom: "chr1".to_string(),
+        start,
+        end,
+        posterior_prob: 0.0,
+        local_fdr: None,
+    }
+}
+
+#[test]
+fn test_lambda_for_bin_uses_chrom_wide_rate_without_local_enrichment() {
+    // Positions evenly spread across the chromosome: no window is denser than the
+    // chromosome-wide average, so lambda_bg should win (within floating-point tolerance).
+    let chrom_length = 100_000;
+    let positions: Vec<u32> = (0..chrom_length).step_by(100).collect();
+    let bin = bin(50_000, 50_200);
+
+    let lambda = lambda_for_bin(&positions, chrom_length, &bin, 1.0);
+    let expected_bg = (positions.len() as f64 / chrom_length as f64) * 200.0;
+    assert!(
+        (lambda - expected_bg).abs() < 1e-6,
+        "expected lambda ~= {}, got {}",
+        expected_bg,
+        lambda
+    );
+}
+
+#[test]
+fn test_lambda_for_bin_picks_up_local_enrichment() {
+    // A dense cluster of positions right around the bin, with an otherwise-empty chromosome:
+    // lambda_bg is ~0, but the 1kb/5kb/10kb windows should pick up the local density and push
+    // lambda well above the bin's own chromosome-wide share.
+    let chrom_length = 1_000_000;
+    let bin = bin(500_000, 500_200);
+    let positions: Vec<u32> = (499_500..500_500).step_by(10).collect();
+
+    let lambda = lambda_for_bin(&positions, chrom_length, &bin, 1.0);
+    let lambda_bg_only = (positions.len() as f64 / chrom_length as f64) * 200.0;
+    assert!(
+        lambda > lambda_bg_only * 10.0,
+        "expected local enrichment to dominate lambda_bg ({}), got {}",
+        lambda_bg_only,
+        lambda
+    );
+}
+
+#[test]
+fn test_lambda_for_bin_scales_with_size_factor() {
+    let chrom_length = 10_000;
+    let positions: Vec<u32> = (0..chrom_length).step_by(50).collect();
+    let bin = bin(5_000, 5_200);
+
+    let lambda_1x = lambda_for_bin(&positions, chrom_length, &bin, 1.0);
+    let lambda_2x = lambda_for_bin(&positions, chrom_length, &bin, 2.0);
+    assert!((lambda_2x - lambda_1x * 2.0).abs() < 1e-6);
+}
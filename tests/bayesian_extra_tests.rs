@@ -4,7 +4,7 @@ use sbpc::bayesian::BayesianModel;
 
 #[test]
 fn test_bayesian_model_min_reads_filter() -> Result<()> {
-    let mut model = BayesianModel::new(0.05, 10);
+    let mut model = BayesianModel::new(0.05, 10, None);
     let bins = vec![
         (
             GenomicRange {
@@ -12,8 +12,10 @@ fn test_bayesian_model_min_reads_filter() -> Result<()> {
                 start: 0,
                 end: 100,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
             5,
+            0.0,
         ), // below min_reads
         (
             GenomicRange {
@@ -21,8 +23,10 @@ fn test_bayesian_model_min_reads_filter() -> Result<()> {
                 start: 100,
                 end: 200,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
             15,
+            0.0,
         ), // above min_reads
     ];
     let total_reads = 100;
@@ -33,7 +37,7 @@ fn test_bayesian_model_min_reads_filter() -> Result<()> {
 
 #[test]
 fn test_bayesian_model_posterior_prob_range() -> Result<()> {
-    let mut model = BayesianModel::new(0.05, 1);
+    let mut model = BayesianModel::new(0.05, 1, None);
     let bins = vec![
         (
             GenomicRange {
@@ -41,8 +45,10 @@ fn test_bayesian_model_posterior_prob_range() -> Result<()> {
                 start: 0,
                 end: 100,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
             50,
+            0.0,
         ),
         (
             GenomicRange {
@@ -50,8 +56,10 @@ fn test_bayesian_model_posterior_prob_range() -> Result<()> {
                 start: 100,
                 end: 200,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
             25,
+            0.0,
         ),
     ];
     let total_reads = 100;
@@ -61,3 +69,149 @@ fn test_bayesian_model_posterior_prob_range() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn test_bayesian_model_fdr_control_sets_local_fdr() -> Result<()> {
+    let mut model = BayesianModel::new(0.05, 1, Some(0.1));
+    let bins = vec![
+        (
+            GenomicRange {
+                chrom: "chr1".to_string(),
+                start: 0,
+                end: 100,
+                posterior_prob: 1.0,
+                local_fdr: None,
+            },
+            50,
+            0.0,
+        ),
+        (
+            GenomicRange {
+                chrom: "chr1".to_string(),
+                start: 100,
+                end: 200,
+                posterior_prob: 1.0,
+                local_fdr: None,
+            },
+            2,
+            0.0,
+        ),
+    ];
+    let total_reads = 100;
+    let significant = model.identify_significant_bins(&bins, total_reads)?;
+    for bin in &significant {
+        assert!(bin.local_fdr.is_some());
+    }
+    // The running expected FDR is non-decreasing, so every selected prefix must stay within alpha.
+    for bin in &significant {
+        assert!(bin.local_fdr.unwrap() <= 0.1);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bayesian_model_local_background_lowers_posterior() -> Result<()> {
+    // Same observed count, but one bin has a local control background close to the observed
+    // count (looks like background) and the other has none (falls back to the global model).
+    let mut model_with_background = BayesianModel::new(0.05, 1, None);
+    let bins_with_background = vec![(
+        GenomicRange {
+            chrom: "chr1".to_string(),
+            start: 0,
+            end: 100,
+            posterior_prob: 1.0,
+            local_fdr: None,
+        },
+        20,
+        19.0,
+    )];
+    let total_reads = 100;
+    let with_background =
+        model_with_background.identify_significant_bins(&bins_with_background, total_reads)?;
+
+    let mut model_without_background = BayesianModel::new(0.05, 1, None);
+    let bins_without_background = vec![(
+        GenomicRange {
+            chrom: "chr1".to_string(),
+            start: 0,
+            end: 100,
+            posterior_prob: 1.0,
+            local_fdr: None,
+        },
+        20,
+        0.0,
+    )];
+    let without_background =
+        model_without_background.identify_significant_bins(&bins_without_background, total_reads)?;
+
+    let posterior_with_background = with_background
+        .first()
+        .map(|bin| bin.posterior_prob)
+        .unwrap_or(0.0);
+    let posterior_without_background = without_background
+        .first()
+        .map(|bin| bin.posterior_prob)
+        .unwrap_or(0.0);
+
+    assert!(posterior_with_background < posterior_without_background);
+    Ok(())
+}
+
+#[test]
+fn test_bayesian_model_mixture_separates_high_and_low_count_bins() -> Result<()> {
+    // A clear bimodal split: most bins near background, a handful far above it. The EM-fitted
+    // mixture should assign the high bins a posterior well above the low ones.
+    let mut model = BayesianModel::new(0.05, 1, None);
+    let mut bins = Vec::new();
+    for i in 0..20 {
+        bins.push((
+            GenomicRange {
+                chrom: "chr1".to_string(),
+                start: i * 100,
+                end: i * 100 + 100,
+                posterior_prob: 1.0,
+                local_fdr: None,
+            },
+            2,
+            0.0,
+        ));
+    }
+    bins.push((
+        GenomicRange {
+            chrom: "chr1".to_string(),
+            start: 2000,
+            end: 2100,
+            posterior_prob: 1.0,
+            local_fdr: None,
+        },
+        100,
+        0.0,
+    ));
+
+    let total_reads = 1000;
+    let significant = model.identify_significant_bins(&bins, total_reads)?;
+
+    assert!(significant.iter().any(|bin| bin.start == 2000));
+    assert!(significant.iter().all(|bin| bin.start != 0));
+    Ok(())
+}
+
+#[test]
+fn test_bayesian_model_fdr_control_empty_when_top_bin_exceeds_alpha() -> Result<()> {
+    let mut model = BayesianModel::new(0.05, 1, Some(0.0));
+    let bins = vec![(
+        GenomicRange {
+            chrom: "chr1".to_string(),
+            start: 0,
+            end: 100,
+            posterior_prob: 1.0,
+            local_fdr: None,
+        },
+        3,
+        0.0,
+    )];
+    let total_reads = 100;
+    let significant = model.identify_significant_bins(&bins, total_reads)?;
+    assert!(significant.is_empty());
+    Ok(())
+}
@@ -4,7 +4,7 @@ use sbpc::bayesian::BayesianModel;
 
 #[test]
 fn test_identify_significant_bins() -> Result<()> {
-    let mut model = BayesianModel::new(0.05, 5);
+    let mut model = BayesianModel::new(0.05, 5, None);
 
     let bins = vec![
         (
@@ -13,8 +13,9 @@ fn test_identify_significant_bins() -> Result<()> {
                 start: 0,
                 end: 100,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
-            20, // High count, should be significant
+            20, 0.0, // High count, should be significant
         ),
         (
             GenomicRange {
@@ -22,8 +23,9 @@ fn test_identify_significant_bins() -> Result<()> {
                 start: 100,
                 end: 200,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
-            2, // Low count, should not be significant
+            2, 0.0, // Low count, should not be significant
         ),
         (
             GenomicRange {
@@ -31,8 +33,9 @@ fn test_identify_significant_bins() -> Result<()> {
                 start: 200,
                 end: 300,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
-            15, // Medium count, might be significant
+            15, 0.0, // Medium count, might be significant
         ),
     ];
 
@@ -60,7 +63,7 @@ fn test_identify_significant_bins() -> Result<()> {
 
 #[test]
 fn test_bayesian_posterior_probability_calculation() -> Result<()> {
-    let mut model = BayesianModel::new(0.05, 5);
+    let mut model = BayesianModel::new(0.05, 5, None);
 
     let bins = vec![
         (
@@ -69,8 +72,9 @@ fn test_bayesian_posterior_probability_calculation() -> Result<()> {
                 start: 0,
                 end: 100,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
-            50, // Very high count
+            50, 0.0, // Very high count
         ),
         (
             GenomicRange {
@@ -78,8 +82,9 @@ fn test_bayesian_posterior_probability_calculation() -> Result<()> {
                 start: 100,
                 end: 200,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
-            25, // High count
+            25, 0.0, // High count
         ),
         (
             GenomicRange {
@@ -87,8 +92,9 @@ fn test_bayesian_posterior_probability_calculation() -> Result<()> {
                 start: 200,
                 end: 300,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
-            10, // Medium count
+            10, 0.0, // Medium count
         ),
         (
             GenomicRange {
@@ -96,8 +102,9 @@ fn test_bayesian_posterior_probability_calculation() -> Result<()> {
                 start: 300,
                 end: 400,
                 posterior_prob: 1.0,
+                local_fdr: None,
             },
-            5, // Low count
+            5, 0.0, // Low count
         ),
     ];
 
@@ -123,14 +130,14 @@ fn test_bayesian_posterior_probability_calculation() -> Result<()> {
 
             let prev_count = bins
                 .iter()
-                .find(|(bin, _)| bin.start == prev_bin.start && bin.end == prev_bin.end)
-                .map(|(_, count)| *count)
+                .find(|(bin, _, _)| bin.start == prev_bin.start && bin.end == prev_bin.end)
+                .map(|(_, count, _)| *count)
                 .unwrap_or(0);
 
             let curr_count = bins
                 .iter()
-                .find(|(bin, _)| bin.start == curr_bin.start && bin.end == curr_bin.end)
-                .map(|(_, count)| *count)
+                .find(|(bin, _, _)| bin.start == curr_bin.start && bin.end == curr_bin.end)
+                .map(|(_, count, _)| *count)
                 .unwrap_or(0);
 
             if prev_count > curr_count {
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use sbpc::bam::GenomicRange;
+use sbpc::bayesian::GenomicPrior;
+use sbpc::broad_domains::segment_broad_domains;
+
+fn bin(chrom: &str, start: u32, end: u32, count: usize) -> (GenomicRange, usize, f64) {
+    (
+        GenomicRange {
+            chrom: chrom.to_string(),
+            start,
+            end,
+            posterior_prob: 0.0,
+            local_fdr: None,
+        },
+        count,
+        0.0,
+    )
+}
+
+#[test]
+fn test_segment_broad_domains_finds_enriched_run() -> Result<()> {
+    // Background on both sides of a clear, contiguous run of enriched bins.
+    let mut bin_counts = Vec::new();
+    for i in 0..20 {
+        bin_counts.push(bin("chr1", i * 100, i * 100 + 100, 2));
+    }
+    for i in 20..30 {
+        bin_counts.push(bin("chr1", i * 100, i * 100 + 100, 100));
+    }
+    for i in 30..50 {
+        bin_counts.push(bin("chr1", i * 100, i * 100 + 100, 2));
+    }
+
+    let prior = GenomicPrior::from_bin_counts(&bin_counts);
+    let domains = segment_broad_domains(&bin_counts, &prior, 50.0)?;
+
+    assert_eq!(domains.len(), 1, "expected a single merged enriched domain");
+    assert_eq!(domains[0].chrom, "chr1");
+    assert_eq!(domains[0].start, 2000);
+    assert_eq!(domains[0].end, 3000);
+    Ok(())
+}
+
+#[test]
+fn test_segment_broad_domains_empty_input() -> Result<()> {
+    let prior = GenomicPrior::from_bin_counts(&[]);
+    let domains = segment_broad_domains(&[], &prior, 50.0)?;
+    assert!(domains.is_empty());
+    Ok(())
+}
@@ -5,8 +5,8 @@ use sbpc::genome::Genome;
 fn test_create_bins_non_overlapping() -> Result<()> {
     let seqnames = vec!["chr1".to_string()];
     let lengths = vec![1000];
-    let genome = Genome { seqnames, lengths };
-    let bins = genome.create_bins(100, 100)?;
+    let genome = Genome::new(seqnames, lengths);
+    let bins = genome.create_bins(100, None)?;
     assert_eq!(bins.len(), 10);
     for (i, bin) in bins.iter().enumerate() {
         assert_eq!(bin.start, i as u32 * 100);
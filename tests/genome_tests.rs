@@ -8,13 +8,11 @@ use tempfile::tempdir;
 fn test_create_bins() -> Result<()> {
     let seqnames = vec!["chr1".to_string(), "chr2".to_string()];
     let lengths = vec![1000, 500];
-    let genome = Genome { seqnames, lengths };
+    let genome = Genome::new(seqnames, lengths);
 
     let step = 100;
-    let slide = 50;
 
-    // With non-overlapping bins, slide is ignored and only step is used
-    let bins = genome.create_bins(step, slide)?;
+    let bins = genome.create_bins(step, None)?;
 
     // chr1: 1000/100 = 10 bins, chr2: 500/100 = 5 bins, total = 15
     assert_eq!(bins.len(), 15, "Expected 15 bins for non-overlapping bins");
@@ -0,0 +1,52 @@
+use anyhow::Result;
+use sbpc::intervals::IntervalIndex;
+use std::fs::File;
+use std::io::Write;
+use tempfile::tempdir;
+
+fn write_bed(path: &std::path::Path, lines: &[(&str, u32, u32)]) -> Result<()> {
+    let mut file = File::create(path)?;
+    for (chrom, start, end) in lines {
+        writeln!(file, "{}\t{}\t{}", chrom, start, end)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_interval_index_overlap() -> Result<()> {
+    let dir = tempdir()?;
+    let bed_path = dir.path().join("regions.bed");
+    write_bed(
+        &bed_path,
+        &[("chr1", 1000, 2000), ("chr1", 5000, 6000), ("chr2", 100, 200)],
+    )?;
+
+    let index = IntervalIndex::from_bed(bed_path.to_str().unwrap())?;
+
+    assert!(index.overlaps("chr1", 1500, 1600));
+    assert!(index.overlaps("chr1", 900, 1100)); // straddles the start
+    assert!(!index.overlaps("chr1", 2000, 3000)); // BED end is exclusive
+    assert!(!index.overlaps("chr1", 2500, 4999));
+    assert!(index.overlaps("chr2", 150, 250));
+    assert!(!index.overlaps("chr3", 0, 100)); // chromosome not in the index
+
+    Ok(())
+}
+
+#[test]
+fn test_interval_index_unsorted_input() -> Result<()> {
+    let dir = tempdir()?;
+    let bed_path = dir.path().join("regions.bed");
+    write_bed(
+        &bed_path,
+        &[("chr1", 5000, 6000), ("chr1", 1000, 2000), ("chr1", 3000, 3500)],
+    )?;
+
+    let index = IntervalIndex::from_bed(bed_path.to_str().unwrap())?;
+
+    assert!(index.overlaps("chr1", 3200, 3300));
+    assert!(index.overlaps("chr1", 5500, 5600));
+    assert!(!index.overlaps("chr1", 2200, 2800));
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use sbpc::bam::GenomicRange;
+use sbpc::peak_caller::Peaks;
+
+fn range(chrom: &str, start: u32, end: u32, posterior_prob: f64, local_fdr: Option<f64>) -> GenomicRange {
+    GenomicRange {
+        chrom: chrom.to_string(),
+        start,
+        end,
+        posterior_prob,
+        local_fdr,
+    }
+}
+
+fn sample_peaks() -> Peaks {
+    let peak = range("chr1", 1000, 1300, 0.95, Some(0.01));
+    let signal_track = vec![
+        (range("chr1", 1000, 1100, 0.0, None), 5.0),
+        (range("chr1", 1100, 1200, 0.0, None), 20.0),
+        (range("chr1", 1200, 1300, 0.0, None), 8.0),
+    ];
+    Peaks {
+        ranges: vec![peak],
+        signal_track,
+    }
+}
+
+#[test]
+fn test_write_narrowpeak_format() {
+    let peaks = sample_peaks();
+    let mut buf = Vec::new();
+    let written = peaks.write_narrowpeak(&mut buf).unwrap();
+    assert_eq!(written, 1);
+
+    let output = String::from_utf8(buf).unwrap();
+    let fields: Vec<&str> = output.trim_end().split('\t').collect();
+    assert_eq!(fields.len(), 10, "narrowPeak has 10 columns: {:?}", fields);
+    assert_eq!(fields[0], "chr1");
+    assert_eq!(fields[1], "1000");
+    assert_eq!(fields[2], "1300");
+    assert_eq!(fields[3], "peak1");
+    assert_eq!(fields[4], "950"); // score = posterior_prob * 1000, clamped to [0, 1000]
+    assert_eq!(fields[5], ".");
+    // The summit should fall in the densest bin, [1100, 1200), offset 150 from start.
+    assert_eq!(fields[6], "20.000000");
+    assert_eq!(fields[9], "150");
+}
+
+#[test]
+fn test_write_narrowpeak_qvalue_missing_is_negative_one() {
+    let mut peaks = sample_peaks();
+    peaks.ranges[0].local_fdr = None;
+    let mut buf = Vec::new();
+    peaks.write_narrowpeak(&mut buf).unwrap();
+
+    let output = String::from_utf8(buf).unwrap();
+    let fields: Vec<&str> = output.trim_end().split('\t').collect();
+    assert_eq!(fields[8], "-1.000000", "qValue should be -1 without --fdr");
+}
+
+#[test]
+fn test_write_bedgraph_format() {
+    let peaks = sample_peaks();
+    let mut buf = Vec::new();
+    let written = peaks.write_bedgraph(&mut buf).unwrap();
+    assert_eq!(written, 3);
+
+    let output = String::from_utf8(buf).unwrap();
+    let mut lines = output.lines();
+    assert_eq!(lines.next().unwrap(), "track type=bedGraph name=\"sbpc signal\"");
+    assert_eq!(lines.next().unwrap(), "chr1\t1000\t1100\t5.000000");
+    assert_eq!(lines.next().unwrap(), "chr1\t1100\t1200\t20.000000");
+    assert_eq!(lines.next().unwrap(), "chr1\t1200\t1300\t8.000000");
+    assert!(lines.next().is_none());
+}
+
+#[test]
+fn test_write_bedgraph_sorts_by_chrom_and_start() {
+    let peaks = Peaks {
+        ranges: vec![],
+        signal_track: vec![
+            (range("chr2", 0, 100, 0.0, None), 1.0),
+            (range("chr1", 100, 200, 0.0, None), 2.0),
+            (range("chr1", 0, 100, 0.0, None), 3.0),
+        ],
+    };
+    let mut buf = Vec::new();
+    peaks.write_bedgraph(&mut buf).unwrap();
+
+    let output = String::from_utf8(buf).unwrap();
+    let lines: Vec<&str> = output.lines().skip(1).collect();
+    assert_eq!(lines, vec!["chr1\t0\t100\t3.000000", "chr1\t100\t200\t2.000000", "chr2\t0\t100\t1.000000"]);
+}
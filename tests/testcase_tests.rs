@@ -0,0 +1,19 @@
+use sbpc::testcase::TestcaseRegion;
+
+#[test]
+fn test_parse_testcase_region() {
+    let region = TestcaseRegion::parse("chr1:1000-2000").unwrap();
+    assert_eq!(region.chrom, "chr1");
+    assert_eq!(region.start, 1000);
+    assert_eq!(region.end, 2000);
+}
+
+#[test]
+fn test_parse_testcase_region_rejects_missing_range() {
+    assert!(TestcaseRegion::parse("chr1").is_err());
+}
+
+#[test]
+fn test_parse_testcase_region_rejects_non_numeric_bounds() {
+    assert!(TestcaseRegion::parse("chr1:abc-2000").is_err());
+}